@@ -15,4 +15,21 @@ fn main() {
         .parse::<i32>()
         .unwrap();
     fs::write(&file_path, (build_number + 1).to_string()).unwrap();
+    // ルールの版は、res/にあるルールの説明のファイル名から決める
+    let rules_versions = fs::read_dir(Path::new(&root_dir).join("res"))
+        .unwrap()
+        .filter_map(|entry| {
+            let file_name = entry.unwrap().file_name().into_string().ok()?;
+            let version = file_name
+                .strip_prefix("game_rules_v")?
+                .strip_suffix(".txt")?;
+            Some(version.to_owned())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        rules_versions.len(),
+        1,
+        "res/ must contain exactly one game_rules_v*.txt"
+    );
+    println!("cargo:rustc-env=RULES_VERSION={}", rules_versions[0]);
 }
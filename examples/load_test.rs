@@ -0,0 +1,185 @@
+//! Measures how quickly the server answers while many rooms are busy at the same time.
+//!
+//! Each room gets one player whose WebSocket sends a heartbeat as soon as the previous one
+//! is acknowledged, so every room is kept busy. Start the server without the
+//! rate limits first, since all the connections come from one address:
+//!
+//! ```sh
+//! cargo run --release -- --port 8080 --http-burst 0 --ws-burst 0 --max-rooms 0
+//! cargo run --release --example load_test -- 127.0.0.1:8080 300 10
+//! ```
+//!
+//! The arguments are the server address, the number of rooms and the number of seconds to run.
+
+use std::{
+    env,
+    error::Error,
+    time::{Duration, Instant},
+};
+
+use futures_util::{SinkExt as _, StreamExt as _};
+use tokio::{
+    io::{AsyncReadExt as _, AsyncWriteExt as _},
+    net::TcpStream,
+};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+
+struct HttpResponse {
+    status: u16,
+    location: Option<String>,
+    body: String,
+}
+
+// 依存を増やさないように、必要な分だけのHTTP/1.1を話す
+async fn request(
+    addr: &str,
+    method: &str,
+    path: &str,
+    body: Option<&str>,
+) -> Result<HttpResponse, BoxError> {
+    let mut stream = TcpStream::connect(addr).await?;
+    let body = body.unwrap_or_default();
+    let head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n",
+        method,
+        path,
+        addr,
+        body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(body.as_bytes()).await?;
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).await?;
+    let raw = String::from_utf8(raw)?;
+    let (head, body) = raw.split_once("\r\n\r\n").ok_or("malformed response")?;
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or("malformed status line")?;
+    let location = lines.find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        name.eq_ignore_ascii_case("location")
+            .then(|| value.trim().to_owned())
+    });
+    Ok(HttpResponse {
+        status,
+        location,
+        body: body.to_owned(),
+    })
+}
+
+/// Creates a room with one player and returns the room ID and the player's private ID.
+async fn create_room(addr: &str, index: usize) -> Result<(String, String), BoxError> {
+    let response = request(addr, "GET", "/room/new", None).await?;
+    let room_id = response
+        .location
+        .as_deref()
+        .and_then(|location| location.strip_prefix("/room/"))
+        .ok_or_else(|| format!("could not create a room (status {})", response.status))?
+        .to_owned();
+    let body = format!(r#"{{"side":"bottom","name":"load{}"}}"#, index);
+    let response = request(
+        addr,
+        "POST",
+        &format!("/room/{}/players", room_id),
+        Some(&body),
+    )
+    .await?;
+    let data = serde_json::from_str::<serde_json::Value>(&response.body)?;
+    let private_id = data["private_id"]
+        .as_str()
+        .ok_or_else(|| format!("could not join the room (status {})", response.status))?
+        .to_owned();
+    Ok((room_id, private_id))
+}
+
+/// Sends heartbeats until `deadline` and returns how long each one took to be acknowledged.
+async fn run_player(
+    addr: String,
+    room_id: String,
+    private_id: String,
+    deadline: Instant,
+) -> Result<Vec<Duration>, BoxError> {
+    let (mut socket, _) = connect_async(format!("ws://{}/room/{}/ws", addr, room_id)).await?;
+    // サーバーは最初にpingを送ってくるので、読んだ時に用意されたpongを送り出してから認証する
+    socket.next().await.ok_or("closed before the ping")??;
+    socket.flush().await?;
+    socket
+        .send(Message::Text(format!(r#"{{"i":"{}"}}"#, private_id)))
+        .await?;
+    let mut latencies = Vec::new();
+    while Instant::now() < deadline {
+        let sent_at = Instant::now();
+        socket.send(Message::Text(r#"{"t":99}"#.to_owned())).await?;
+        loop {
+            match socket.next().await.ok_or("closed by the server")?? {
+                // 部屋の出来事も届くので、heartbeatへの応答だけを数える
+                Message::Text(text) if text == r#"{"t":100}"# => break,
+                Message::Close(frame) => return Err(format!("closed: {:?}", frame).into()),
+                _ => {}
+            }
+        }
+        latencies.push(sent_at.elapsed());
+    }
+    socket.close(None).await?;
+    Ok(latencies)
+}
+
+fn percentile(sorted: &[Duration], ratio: f64) -> Duration {
+    let index = ((sorted.len() - 1) as f64 * ratio).round() as usize;
+    sorted[index]
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    let mut args = env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| "127.0.0.1:8080".to_owned());
+    let room_count = args.next().map_or(Ok(300), |arg| arg.parse())?;
+    let seconds = args.next().map_or(Ok(10), |arg| arg.parse())?;
+    println!("Creating {} rooms on {}", room_count, addr);
+    let mut rooms = Vec::with_capacity(room_count);
+    for index in 0..room_count {
+        rooms.push(create_room(&addr, index).await?);
+    }
+    println!("Sending heartbeats for {} seconds", seconds);
+    let started_at = Instant::now();
+    let deadline = started_at + Duration::from_secs(seconds);
+    let tasks = rooms
+        .into_iter()
+        .map(|(room_id, private_id)| {
+            tokio::spawn(run_player(addr.clone(), room_id, private_id, deadline))
+        })
+        .collect::<Vec<_>>();
+    let mut latencies = Vec::new();
+    let mut failures = 0;
+    for task in tasks {
+        match task.await? {
+            Ok(player_latencies) => latencies.extend(player_latencies),
+            Err(error) => {
+                failures += 1;
+                eprintln!("A player failed: {}", error);
+            }
+        }
+    }
+    let elapsed = started_at.elapsed();
+    if latencies.is_empty() {
+        return Err("no heartbeat was acknowledged".into());
+    }
+    latencies.sort_unstable();
+    println!("Acknowledged heartbeats: {}", latencies.len());
+    println!(
+        "Throughput: {:.0} heartbeats/s",
+        latencies.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!("p50: {:?}", percentile(&latencies, 0.5));
+    println!("p99: {:?}", percentile(&latencies, 0.99));
+    println!("max: {:?}", latencies[latencies.len() - 1]);
+    if failures > 0 {
+        println!("Failed players: {}", failures);
+    }
+    Ok(())
+}
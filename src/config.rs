@@ -0,0 +1,531 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs,
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    sync::OnceLock,
+};
+
+use clap::Parser;
+use serde::{
+    de::{
+        value::{Error as DeError, StrDeserializer},
+        DeserializeOwned,
+    },
+    Deserialize,
+};
+
+use crate::{
+    handler::game::{GameSessionBoardStyle, TeamMode, Visibility, MAX_BOARD_SIZE, MIN_BOARD_SIZE},
+    util::logger::{Level, LogFormat},
+};
+
+/// 設定は「コマンドライン引数 > 環境変数 > 設定ファイル > 既定値」の順に優先されます。
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub player: PlayerConfig,
+    pub room: RoomConfig,
+    pub matchmaking: MatchmakingConfig,
+    pub limit: LimitConfig,
+    pub log: LogConfig,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind_address: IpAddr,
+    pub port: u16,
+    /// Seconds to wait for open connections to close on shutdown.
+    pub shutdown_timeout: u64,
+    /// File to save rooms to on shutdown and restore them from on startup.
+    pub state_file: Option<PathBuf>,
+    /// Bearer token for the `/admin` API. The API is disabled if unset.
+    pub admin_token: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PlayerConfig {
+    /// Seconds without a heartbeat before a player is marked inactive.
+    pub inactive_threshold: u64,
+    /// Seconds without a heartbeat before a player is removed from the room.
+    pub kick_threshold: u64,
+    /// Seconds between the WebSocket pings sent to each connection.
+    pub ping_interval: u64,
+    /// Seconds to wait for a pong, or any other message, after a ping before closing the connection.
+    pub pong_timeout: u64,
+    /// Seconds a player keeps their seat after their last connection closes.
+    pub reconnect_grace: u64,
+    /// Whether to close a player's older connections when they connect again.
+    pub kick_duplicate_connections: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RoomConfig {
+    pub queue_message_limit: usize,
+    pub board_size: usize,
+    pub board_style: GameSessionBoardStyle,
+    pub team_player_limit: usize,
+    pub team_mode: TeamMode,
+    pub visibility: Visibility,
+    /// Seconds a room is kept without players before it is closed. 0 keeps empty rooms forever.
+    pub empty_room_timeout: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MatchmakingConfig {
+    /// Seconds a player waits in the queue before giving up.
+    pub timeout: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitConfig {
+    /// HTTP requests each IP address can make at once. 0 disables the limit.
+    pub http_burst: u32,
+    /// HTTP requests per second each IP address can make after the burst.
+    pub http_rate: f64,
+    /// WebSocket messages each connection can send at once. 0 disables the limit.
+    pub ws_burst: u32,
+    /// WebSocket messages per second each connection can send after the burst.
+    pub ws_rate: f64,
+    /// Number of rooms that can exist at the same time. 0 disables the limit.
+    pub max_rooms: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub color: bool,
+    /// The most detailed level written, unless overridden in `modules`.
+    pub level: Level,
+    pub format: LogFormat,
+    /// Levels for specific targets, such as `ws` or `handler::game`.
+    pub modules: HashMap<String, Level>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: {
+                #[cfg(debug_assertions)]
+                {
+                    IpAddr::V4(Ipv4Addr::LOCALHOST)
+                }
+                #[cfg(not(debug_assertions))]
+                {
+                    IpAddr::V4(Ipv4Addr::UNSPECIFIED)
+                }
+            },
+            port: 80,
+            shutdown_timeout: 10,
+            state_file: None,
+            admin_token: None,
+        }
+    }
+}
+
+impl Default for PlayerConfig {
+    fn default() -> Self {
+        Self {
+            inactive_threshold: 30,
+            kick_threshold: 45,
+            ping_interval: 10,
+            pong_timeout: 10,
+            reconnect_grace: 30,
+            kick_duplicate_connections: false,
+        }
+    }
+}
+
+impl Default for RoomConfig {
+    fn default() -> Self {
+        Self {
+            queue_message_limit: 16,
+            board_size: 8,
+            board_style: Default::default(),
+            team_player_limit: 2,
+            team_mode: Default::default(),
+            visibility: Default::default(),
+            empty_room_timeout: 300,
+        }
+    }
+}
+
+impl Default for MatchmakingConfig {
+    fn default() -> Self {
+        Self { timeout: 120 }
+    }
+}
+
+impl Default for LimitConfig {
+    fn default() -> Self {
+        Self {
+            http_burst: 60,
+            http_rate: 10.0,
+            ws_burst: 30,
+            ws_rate: 10.0,
+            max_rooms: 1000,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            color: true,
+            level: Level::Info,
+            format: LogFormat::Human,
+            modules: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Parser)]
+#[command(version, about = "numbers game server")]
+struct Args {
+    /// Path to a TOML configuration file
+    #[arg(short, long, env = "NUMBERS_CONFIG")]
+    config: Option<PathBuf>,
+    /// Address to listen on
+    #[arg(long, env = "NUMBERS_BIND_ADDRESS")]
+    bind_address: Option<IpAddr>,
+    /// Port to listen on
+    #[arg(short, long, env = "NUMBERS_PORT")]
+    port: Option<u16>,
+    /// Seconds to wait for open connections to close on shutdown
+    #[arg(long, env = "NUMBERS_SHUTDOWN_TIMEOUT")]
+    shutdown_timeout: Option<u64>,
+    /// File to save rooms to on shutdown and restore them from on startup
+    #[arg(long, env = "NUMBERS_STATE_FILE")]
+    state_file: Option<PathBuf>,
+    /// Bearer token for the admin API, which is disabled if unset
+    #[arg(long, env = "NUMBERS_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
+    /// Seconds without a heartbeat before a player is marked inactive
+    #[arg(long, env = "NUMBERS_PLAYER_INACTIVE_THRESHOLD")]
+    player_inactive_threshold: Option<u64>,
+    /// Seconds without a heartbeat before a player is removed from the room
+    #[arg(long, env = "NUMBERS_PLAYER_KICK_THRESHOLD")]
+    player_kick_threshold: Option<u64>,
+    /// Seconds between the WebSocket pings sent to each connection
+    #[arg(long, env = "NUMBERS_PLAYER_PING_INTERVAL")]
+    player_ping_interval: Option<u64>,
+    /// Seconds to wait for a pong after a ping before closing the connection
+    #[arg(long, env = "NUMBERS_PLAYER_PONG_TIMEOUT")]
+    player_pong_timeout: Option<u64>,
+    /// Seconds a player keeps their seat after their last connection closes
+    #[arg(long, env = "NUMBERS_PLAYER_RECONNECT_GRACE")]
+    player_reconnect_grace: Option<u64>,
+    /// Whether to close a player's older connections when they connect again
+    #[arg(long, env = "NUMBERS_PLAYER_KICK_DUPLICATE_CONNECTIONS")]
+    player_kick_duplicate_connections: Option<bool>,
+    /// Number of room events buffered for each room
+    #[arg(long, env = "NUMBERS_QUEUE_MESSAGE_LIMIT")]
+    queue_message_limit: Option<usize>,
+    /// Default board size of new rooms
+    #[arg(long, env = "NUMBERS_BOARD_SIZE")]
+    board_size: Option<usize>,
+    /// Default board style of new rooms (checker or chess)
+    #[arg(long, env = "NUMBERS_BOARD_STYLE", value_parser = parse_enum::<GameSessionBoardStyle>)]
+    board_style: Option<GameSessionBoardStyle>,
+    /// Default player limit per side of new rooms
+    #[arg(long, env = "NUMBERS_TEAM_PLAYER_LIMIT")]
+    team_player_limit: Option<usize>,
+    /// Default team mode of new rooms (free_for_all, rotation or captain)
+    #[arg(long, env = "NUMBERS_TEAM_MODE", value_parser = parse_enum::<TeamMode>)]
+    team_mode: Option<TeamMode>,
+    /// Default visibility of new rooms (public, unlisted or private)
+    #[arg(long, env = "NUMBERS_VISIBILITY", value_parser = parse_enum::<Visibility>)]
+    visibility: Option<Visibility>,
+    /// Seconds a room is kept without players before it is closed (0 keeps it forever)
+    #[arg(long, env = "NUMBERS_EMPTY_ROOM_TIMEOUT")]
+    empty_room_timeout: Option<u64>,
+    /// Seconds a player waits in the matchmaking queue before giving up
+    #[arg(long, env = "NUMBERS_MATCHMAKING_TIMEOUT")]
+    matchmaking_timeout: Option<u64>,
+    /// HTTP requests each IP address can make at once (0 disables the limit)
+    #[arg(long, env = "NUMBERS_HTTP_BURST")]
+    http_burst: Option<u32>,
+    /// HTTP requests per second each IP address can make after the burst
+    #[arg(long, env = "NUMBERS_HTTP_RATE")]
+    http_rate: Option<f64>,
+    /// WebSocket messages each connection can send at once (0 disables the limit)
+    #[arg(long, env = "NUMBERS_WS_BURST")]
+    ws_burst: Option<u32>,
+    /// WebSocket messages per second each connection can send after the burst
+    #[arg(long, env = "NUMBERS_WS_RATE")]
+    ws_rate: Option<f64>,
+    /// Number of rooms that can exist at the same time (0 disables the limit)
+    #[arg(long, env = "NUMBERS_MAX_ROOMS")]
+    max_rooms: Option<usize>,
+    /// Whether to colorize log output
+    #[arg(long, env = "NUMBERS_LOG_COLOR")]
+    log_color: Option<bool>,
+    /// Most detailed log level written (error, warn, info, debug or trace)
+    #[arg(long, env = "NUMBERS_LOG_LEVEL", value_parser = parse_enum::<Level>)]
+    log_level: Option<Level>,
+    /// Log output format (human or json)
+    #[arg(long, env = "NUMBERS_LOG_FORMAT", value_parser = parse_enum::<LogFormat>)]
+    log_format: Option<LogFormat>,
+    /// Log levels for specific targets, e.g. "ws=debug,http=warn"
+    #[arg(long, env = "NUMBERS_LOG_MODULES", value_parser = parse_module_levels)]
+    log_modules: Option<HashMap<String, Level>>,
+}
+
+fn parse_enum<T>(value: &str) -> Result<T, String>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(StrDeserializer::<DeError>::new(value)).map_err(|error| error.to_string())
+}
+
+fn parse_module_levels(value: &str) -> Result<HashMap<String, Level>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((module, level)) => Ok((module.trim().to_owned(), parse_enum(level.trim())?)),
+            None => Err(format!("expected module=level, found {:?}", pair)),
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct ConfigError(String);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ConfigError {}
+
+impl Config {
+    /// Builds the configuration from command-line arguments, environment variables
+    /// and the optional configuration file.
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let args = Args::parse();
+        let mut config = match args.config {
+            Some(ref path) => Self::parse(&fs::read_to_string(path)?)?,
+            None => Self::default(),
+        };
+        macro_rules! override_with {
+            ($($arg:ident => $($field:ident).+),+ $(,)?) => {
+                $(if let Some(value) = args.$arg {
+                    config.$($field).+ = value;
+                })+
+            };
+        }
+        override_with! {
+            bind_address => server.bind_address,
+            port => server.port,
+            shutdown_timeout => server.shutdown_timeout,
+            player_inactive_threshold => player.inactive_threshold,
+            player_kick_threshold => player.kick_threshold,
+            player_ping_interval => player.ping_interval,
+            player_pong_timeout => player.pong_timeout,
+            player_reconnect_grace => player.reconnect_grace,
+            player_kick_duplicate_connections => player.kick_duplicate_connections,
+            queue_message_limit => room.queue_message_limit,
+            board_size => room.board_size,
+            board_style => room.board_style,
+            team_player_limit => room.team_player_limit,
+            team_mode => room.team_mode,
+            visibility => room.visibility,
+            empty_room_timeout => room.empty_room_timeout,
+            matchmaking_timeout => matchmaking.timeout,
+            http_burst => limit.http_burst,
+            http_rate => limit.http_rate,
+            ws_burst => limit.ws_burst,
+            ws_rate => limit.ws_rate,
+            max_rooms => limit.max_rooms,
+            log_color => log.color,
+            log_level => log.level,
+            log_format => log.format,
+            log_modules => log.modules,
+        }
+        if args.state_file.is_some() {
+            config.server.state_file = args.state_file;
+        }
+        if args.admin_token.is_some() {
+            config.server.admin_token = args.admin_token;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Reads a configuration file. Sections and keys that are left out keep their defaults.
+    pub fn parse(text: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    fn validate(&self) -> Result<(), ConfigError> {
+        if self
+            .server
+            .admin_token
+            .as_ref()
+            .is_some_and(|token| token.len() < 16)
+        {
+            return Err(ConfigError(
+                "server.admin_token must be at least 16 characters long".to_owned(),
+            ));
+        }
+        // 大きすぎる盤の対局は、棋譜として書き出しても読み込めない
+        if !(MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&self.room.board_size) {
+            return Err(ConfigError(format!(
+                "room.board_size must be between {} and {}",
+                MIN_BOARD_SIZE, MAX_BOARD_SIZE
+            )));
+        }
+        // 0人では誰も席につけない
+        if self.room.team_player_limit == 0 {
+            return Err(ConfigError(
+                "room.team_player_limit must be 1 or above".to_owned(),
+            ));
+        }
+        if self.room.queue_message_limit == 0 {
+            return Err(ConfigError(
+                "room.queue_message_limit must be 1 or above".to_owned(),
+            ));
+        }
+        if self.matchmaking.timeout == 0 {
+            return Err(ConfigError(
+                "matchmaking.timeout must be 1 or above".to_owned(),
+            ));
+        }
+        for (name, burst, rate) in [
+            ("http", self.limit.http_burst, self.limit.http_rate),
+            ("ws", self.limit.ws_burst, self.limit.ws_rate),
+        ] {
+            // 制限を無効にしている場合は、rateを使わない
+            if burst > 0 && !(rate.is_finite() && rate > 0.0) {
+                return Err(ConfigError(format!(
+                    "limit.{}_rate must be a positive number",
+                    name
+                )));
+            }
+        }
+        if self.player.ping_interval == 0 {
+            return Err(ConfigError(
+                "player.ping_interval must be 1 or above".to_owned(),
+            ));
+        }
+        // 0にすると、最初のpingへの応答を待つ期限がすぐに切れてしまう
+        if self.player.pong_timeout == 0 {
+            return Err(ConfigError(
+                "player.pong_timeout must be 1 or above".to_owned(),
+            ));
+        }
+        if self.player.kick_threshold < self.player.inactive_threshold {
+            return Err(ConfigError(
+                "player.kick_threshold must not be less than player.inactive_threshold".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/// # This function will panic if called more than once.
+pub fn init(config: Config) {
+    CONFIG.set(config).expect("config is already initialized");
+}
+
+/// Returns the server configuration, or the defaults if `init` has not been called.
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_example_in_the_readme_is_valid() {
+        let readme = include_str!("../README.md");
+        let example = readme
+            .split_once("```toml\n")
+            .and_then(|(_, rest)| rest.split_once("```"))
+            .map(|(example, _)| example)
+            .unwrap();
+        let config = Config::parse(example).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.room.team_mode, TeamMode::FreeForAll);
+        assert_eq!(config.log.modules["ws"], Level::Debug);
+    }
+
+    #[test]
+    fn missing_keys_keep_their_defaults() {
+        let config = Config::parse("[player]\npong_timeout = 3\n").unwrap();
+        assert_eq!(config.player.pong_timeout, 3);
+        assert_eq!(
+            config.player.ping_interval,
+            PlayerConfig::default().ping_interval
+        );
+        assert_eq!(config.server.port, ServerConfig::default().port);
+        Config::parse("").unwrap().validate().unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!(Config::parse("[player]\npong_timout = 3\n").is_err());
+        assert!(Config::parse("[players]\n").is_err());
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for text in [
+            "[server]\nadmin_token = \"too short\"\n",
+            "[room]\nboard_size = 6\n",
+            "[room]\nboard_size = 17\n",
+            "[room]\nqueue_message_limit = 0\n",
+            "[matchmaking]\ntimeout = 0\n",
+            "[limit]\nhttp_rate = 0.0\n",
+            "[limit]\nws_rate = nan\n",
+            "[player]\nping_interval = 0\n",
+            "[player]\ninactive_threshold = 60\nkick_threshold = 30\n",
+        ] {
+            let config = Config::parse(text).unwrap();
+            assert!(config.validate().is_err(), "{:?} was accepted", text);
+        }
+        // 制限を無効にしていれば、rateは使わない
+        let config = Config::parse("[limit]\nhttp_burst = 0\nhttp_rate = 0.0\n").unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn a_zero_pong_timeout_is_rejected() {
+        let config = Config::parse("[player]\npong_timeout = 0\n").unwrap();
+        assert!(config.validate().is_err());
+        let config = Config::parse("[player]\npong_timeout = 1\n").unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn a_zero_team_player_limit_is_rejected() {
+        let config = Config::parse("[room]\nteam_player_limit = 0\n").unwrap();
+        assert!(config.validate().is_err());
+        let config = Config::parse("[room]\nteam_player_limit = 1\n").unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn module_levels_are_parsed() {
+        let levels = parse_module_levels("ws=debug, http = warn,").unwrap();
+        assert_eq!(levels.len(), 2);
+        assert_eq!(levels["ws"], Level::Debug);
+        assert_eq!(levels["http"], Level::Warn);
+        assert!(parse_module_levels("ws").is_err());
+        assert!(parse_module_levels("ws=loud").is_err());
+    }
+}
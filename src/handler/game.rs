@@ -1,11 +1,49 @@
 // game/のファイルについて、よく自分でもその内容や目的を忘れてしまうので、
 // ファイルの内容とその歴史について、ここに備忘録を残しておきます。
 
+pub mod admin;
+/// game::adminは、運営者が部屋の一覧や内部状態を確認し、部屋を閉じたりプレイヤーをキックしたりするためのAPIです。
+//    サーバーの中で何が起きているのかを外から知る手段がなかったため、設定したトークンで保護されたAPIとして追加しました。
+mod archive;
+/// game::archiveは、対局の初期設定と手の一覧を記録し、任意の手数の局面をルールに沿って再現します。
+//    終わった対局を振り返る手段がなかったため、部屋とは別に対局ごとの記録を残すようにしました。
+//    局面の再現にGameSessionを使うとプレイヤーや手番の担当者が必要になるため、
+//    盤面のルールだけをgame::session::boardに切り出し、GameSessionと共有しています。
 pub mod http;
 /// game::httpは、部屋・プレイヤーの作成、WebSocket通信への誘導を担っています。
 //    一旦は、game::wsの事情により、このファイルがゲームの処理も請け負っていました。
 //    その後、game::wsの説明で後述する理由により、
 //    game::wsファイル内で受信->送信の処理ができるようになり、このファイルの機能は元通りになりました。
+pub mod identity;
+/// game::identityは、部屋をまたいでプレイヤーを識別するためのtokenとIdentityを管理しています。
+//    元々は部屋に参加するたびにIDと名前が新しく作られていたため、
+//    ページを再読み込みするとプレイヤーとしての席を失ってしまっていました。
+//    そこで、HttpOnlyなCookieとしてtokenを発行し、同じ部屋に再参加した際に同じ席と名前を取り戻せるようにしました。
+//    Cookieを持たないリクエストのたびに作られたIdentityが溜まり続けていたため、
+//    一度も席に着かなかったIdentityは一日で消えるようにしました。
+pub mod lobby;
+/// game::lobbyは、公開された部屋の一覧と、その変化をWebSocketで知らせるロビーを担っています。
+//    部屋はURLを共有しない限り見つけられなかったため、公開設定の部屋を一覧できるようにしました。
+//    部屋への変更は全て部屋のタスクが処理するので、その処理をきっかけに一覧の差分を計算しています。
+pub mod matchmaking;
+/// game::matchmakingは、部屋のURLを知らないプレイヤー同士を待ち行列から組み合わせ、新しい部屋へ案内します。
+//    公開された部屋の一覧ができても、相手が部屋を作るのを待つ必要があったため、希望する設定で並ぶだけで対局できるようにしました。
+//    結果はロングポーリングで受け取るため、WebSocketに対応していない環境からも使えます。
+pub mod notation;
+/// game::notationは、対局の記録を人が読めるテキストの棋譜として書き出し、棋譜から再生可能な記録を作り直します。
+//    大会の対局をサーバーの外で保管できるように、ヘッダーと手の一覧からなる独自の棋譜形式を定めました。
+//    読み込んだ棋譜の手はgame::session::boardで一手ずつ検証するため、ルールに反する棋譜は記録になりません。
+pub mod persistence;
+/// game::persistenceは、サーバーの再起動をまたいで部屋とIdentityを引き継ぐために、それらをファイルへ保存・復元します。
+//    再起動のたびに進行中の対局が消えてしまっていたため、シャットダウン時に状態を書き出し、起動時に読み込むようにしました。
+//    GameSessionの非公開なフィールドに触れる必要があるため、保存用の形への変換はgame::session::snapshotに置いています。
+pub mod rating;
+/// game::ratingは、レーティング戦の結果からプレイヤーのレーティングを計算し、成績とランキングを公開します。
+//    勝敗が記録されるようになったので、Identityのpublic_idごとにEloレーティングと対局の履歴を残すようにしました。
+//    チーム戦では、それぞれのチームの平均レーティング同士の対局として計算しています。
+pub mod replay;
+/// game::replayは、対局の記録と局面を返すAPIと、複数人で同じ局面を見ながら手を進める再生用のWebSocketを担っています。
+//    指導の際に、対局を一手ずつ進めたり戻したりしながら全員で同じ局面を見られるように追加しました。
 mod session;
 /// game::sessionは、GameSessionやPlayerDataなどのゲームのセッションに関する情報を保持するstructを定義しています。
 //    元々はgame::structsというファイルに定義されていて、いくつかに分断されていましたが、
@@ -13,6 +51,12 @@ mod session;
 //    その後、SelectPlayerという、「上のプレイヤー」と「下のプレイヤー」を、
 //    「片方」と「その反対」としてアクセスできるようにするstructが作成されましたが、その関数のほとんどが使用されておらず
 //    不必要と判断され、get_pieces_pair_mutだけを残して廃止されました。
+//    また、全ての部屋を一つのRwLockで守っていたため、一つの部屋の処理が他の全ての部屋を待たせていました。
+//    そこで部屋ごとにロックを持たせ、部屋の一覧のロックは部屋の追加・削除・検索の間だけ取るようにしました。
+//    さらに、ロックの代わりに部屋ごとのタスクがGameSessionを所有し、mpscで届く命令を順に処理するようにしました。
+//    これにより、同じ部屋での出来事の順番が常に決まり、「部屋が存在するはず」という前提で動く関数もなくなりました。
+//    部屋ごとに毎秒動いていたheartbeatの確認も、プレイヤーごとの期限を一つのタスクで管理し、
+//    期限が来たプレイヤーの部屋にだけ命令を送るようにしたので、誰もいない部屋はまったく動きません。
 mod structure;
 /// game::structureは、主にgame::httpで使用するSerialize/Deserializeが可能なstructを定義しています。
 //    こちらもgame::sessionと同じように、元々はgame::structsというファイルに定義されていました。
@@ -26,10 +70,20 @@ mod ws;
 //    プレイヤーの行動の処理(=ゲームの処理)をgame::httpが担っていました。
 //    しかし、WebSocketMessagingというenumの誕生と、tokio::selectマクロの存在によって、
 //    「受信した内容を元に送信する」ことが可能になり、このファイルの機能は元通りになりました。
+//    その後、接続が生きているかどうかをheartbeatだけでなくWebSocketのpingとpongでも確かめるようになり、
+//    プレイヤーの最後の接続が閉じてから一定時間戻ってこなければ、席を空けるようにしました。
+//    さらに、席と接続を結び付け、同じプレイヤーが別の場所から接続した時に古い接続を閉じられるようにしました。
+//    最後に、接続を「最初のpong待ち」「未認証」「認証済み」「閉じる途中」の段階に分けてそれぞれに期限を設け、
+//    クライアントが何を送ってきてもpanicしないように作り直しました。
+pub use self::session::{
+    map::count_rooms_and_players, GameSessionBoardStyle, Side, TeamMode, Visibility,
+};
 
-const DEFAULT_BOARD_SIZE: usize = 8;
-const DEFAULT_TEAM_PLAYER_LIMIT: usize = 2;
+// 調整可能な値はcrate::configに移動しました。ここにはゲームのルールに関わる値だけを置きます。
 const INITIAL_NUMBER: u8 = 3;
-const QUEUE_MESSAGE_LIMIT: usize = 16;
-const PLAYER_INACTIVE_THRESHOLD: u64 = 30;
-const PLAYER_KICK_THRESHOLD: u64 = 45;
+pub const MIN_BOARD_SIZE: usize = 7;
+// 棋譜で列をアルファベット1文字で表すため、それより大きくはできない
+pub const MAX_BOARD_SIZE: usize = 16;
+// ルールの版。build.rsがres/game_rules_v*.txtのファイル名から取り出す
+// ルールを変えた場合は、古い棋譜を読み込めないようにファイル名の版を上げる
+const RULES_VERSION: &str = env!("RULES_VERSION");
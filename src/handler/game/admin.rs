@@ -0,0 +1,151 @@
+use axum::{
+    extract::{rejection::JsonRejection, Path, Request},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+    Json,
+};
+use futures_util::future;
+use uuid::Uuid;
+
+use crate::{config, util::SimpleResponse};
+
+use super::{
+    http::{error_response, get_room_data},
+    lobby::notify_lobby,
+    session::map::{self, get_room, get_rooms},
+    structure::{AdminRoomDetail, AdminRoomSummary, NoticeRequest, PlayerCounts, ResultData},
+    Side,
+};
+
+// お知らせは接続中の全員に送られるので、長すぎるものは受け付けない
+const MAX_NOTICE_LENGTH: usize = 500;
+
+/// Compares in time that depends only on the lengths, so the token can't be guessed byte by byte.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn admin_auth(headers: HeaderMap, req: Request, next: Next) -> Response {
+    // トークンが設定されていない場合は、管理用のAPIが存在しないかのように振る舞う
+    let Some(ref admin_token) = config::get().server.admin_token else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    let provided_token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match provided_token {
+        Some(token) if constant_time_eq(token.as_bytes(), admin_token.as_bytes()) => {
+            next.run(req).await
+        }
+        _ => error_response(StatusCode::UNAUTHORIZED, "INVALID_ADMIN_TOKEN"),
+    }
+}
+
+fn success_response() -> Response {
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(ResultData {
+            success: true,
+            message: None,
+        }),
+    }
+    .into_response()
+}
+
+pub async fn list_rooms() -> Response {
+    let rooms = future::join_all(get_rooms().into_iter().map(|(room_id, room)| async move {
+        room.inspect(move |session| AdminRoomSummary {
+            room_id,
+            config: session.get_config(),
+            player_counts: PlayerCounts {
+                top: session.get_player_data(Side::Top).len(),
+                bottom: session.get_player_data(Side::Bottom).len(),
+            },
+            age_seconds: session.get_age().as_secs(),
+            phase: session.get_phase(),
+            is_locked: session.is_locked(),
+        })
+        .await
+    }))
+    .await
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>();
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(rooms),
+    }
+    .into_response()
+}
+
+pub async fn room_detail(Path(room_id): Path<Uuid>) -> Response {
+    let detail = match get_room(room_id) {
+        Some(room) => {
+            room.inspect(move |session| AdminRoomDetail {
+                room_id,
+                age_seconds: session.get_age().as_secs(),
+                config: session.get_config(),
+                state: get_room_data(room_id, session),
+            })
+            .await
+        }
+        None => None,
+    };
+    let Some(detail) = detail else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID");
+    };
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(detail),
+    }
+    .into_response()
+}
+
+pub async fn close_room(Path(room_id): Path<Uuid>) -> Response {
+    // 部屋のタスクが終わってGameSessionがdropすると、接続中のWebSocketには"Room Closed"の閉じるフレームが送られる
+    if !map::close_room(room_id).await {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID");
+    }
+    notify_lobby();
+    success_response()
+}
+
+pub async fn kick_player(Path((room_id, public_id)): Path<(Uuid, Uuid)>) -> Response {
+    let Some(room) = get_room(room_id) else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID");
+    };
+    match room
+        .update(move |session| session.admin_kick_player(public_id).is_ok())
+        .await
+    {
+        Some(true) => {}
+        Some(false) => return error_response(StatusCode::NOT_FOUND, "INVALID_PLAYER_ID"),
+        None => return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID"),
+    }
+    success_response()
+}
+
+pub async fn broadcast_notice(body: Result<Json<NoticeRequest>, JsonRejection>) -> Response {
+    let Ok(Json(NoticeRequest { message })) = body else {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_REQUEST_BODY");
+    };
+    if message.trim().is_empty() {
+        return error_response(StatusCode::BAD_REQUEST, "EMPTY_MESSAGE");
+    }
+    if message.chars().count() > MAX_NOTICE_LENGTH {
+        return error_response(StatusCode::BAD_REQUEST, "MESSAGE_TOO_LONG");
+    }
+    for (_, room) in get_rooms() {
+        let message = message.clone();
+        // 途中で閉じられた部屋には送らなくてよい
+        let _ = room
+            .inspect(move |session| session.send_notice(&message))
+            .await;
+    }
+    success_response()
+}
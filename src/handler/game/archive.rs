@@ -0,0 +1,232 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::OnceLock,
+};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::util::get_unix_time;
+
+use super::session::{
+    board::{self, MoveOutcome, Pieces},
+    GameSessionBoardStyle, MoveRejection, Position, Side,
+};
+
+// このサーバーで行われた対局は、終わったものから古い順に捨てる
+const MAX_ARCHIVED_GAMES: usize = 1000;
+// 読み込まれた棋譜は、対局の記録を押し出さないように別に数え、古い順に捨てる
+const MAX_IMPORTED_GAMES: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedPlayer {
+    pub public_id: Uuid,
+    pub name: String,
+    pub side: Side,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct RecordedMove {
+    pub side: Side,
+    /// 駒を動かしたプレイヤーのpublic_id
+    pub public_id: Uuid,
+    pub from: Position,
+    pub to: Position,
+}
+
+/// 一局分の記録です。初期配置は盤の大きさと配置から決まるので、手の一覧だけを持ちます。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub game_id: Uuid,
+    pub room_id: Uuid,
+    pub board_size: usize,
+    pub board_style: GameSessionBoardStyle,
+    /// 対局を始めた時に席についていたプレイヤー
+    pub players: Vec<RecordedPlayer>,
+    pub first_turn: Side,
+    pub moves: Vec<RecordedMove>,
+    pub winner: Option<Side>,
+    /// UNIXエポックからの秒数
+    pub started_at: u64,
+    /// 決着したか、途中で打ち切られた時刻。打ち切られた場合、winnerはNoneのまま
+    pub finished_at: Option<u64>,
+}
+
+/// The board after some of the moves of a record.
+pub struct ReplayedPosition {
+    pub pieces: Pieces,
+    pub current_turn: Side,
+}
+
+/// Why a record could not be replayed. Only records that did not come from this server can fail.
+#[derive(Debug, Clone, Copy)]
+pub enum ReplayError {
+    /// The move at `ply` was played by the side whose turn it was not.
+    WrongSide { ply: usize },
+    /// The move at `ply` broke the rules.
+    IllegalMove {
+        ply: usize,
+        rejection: MoveRejection,
+    },
+}
+
+impl std::fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Self::WrongSide { ply } => write!(f, "move {} was played out of turn", ply + 1),
+            Self::IllegalMove { ply, rejection } => {
+                write!(f, "move {} is illegal ({})", ply + 1, rejection.as_str())
+            }
+        }
+    }
+}
+
+impl GameRecord {
+    /// Returns whether the game was stopped before either side won.
+    pub fn is_aborted(&self) -> bool {
+        self.winner.is_none() && self.finished_at.is_some()
+    }
+
+    /// Returns whether the record was imported from a file rather than played on this server.
+    pub fn is_imported(&self) -> bool {
+        self.room_id.is_nil()
+    }
+
+    pub fn initial_position(&self) -> ReplayedPosition {
+        ReplayedPosition {
+            pieces: board::create_initial_pieces(self.board_size, self.board_style),
+            current_turn: self.first_turn,
+        }
+    }
+
+    /// Replays the first `ply` moves through the rules. `ply` must not exceed the number of moves.
+    pub fn position_at(&self, ply: usize) -> Result<ReplayedPosition, ReplayError> {
+        let mut position = self.initial_position();
+        for (i, recorded) in self.moves[..ply].iter().enumerate() {
+            if recorded.side != position.current_turn {
+                return Err(ReplayError::WrongSide { ply: i });
+            }
+            let outcome = board::apply_move(
+                &mut position.pieces,
+                recorded.side,
+                recorded.from,
+                recorded.to,
+            )
+            .map_err(|rejection| ReplayError::IllegalMove { ply: i, rejection })?;
+            if outcome == MoveOutcome::TurnFinished {
+                position.current_turn = position.current_turn.opposite();
+            }
+        }
+        Ok(position)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GameArchive {
+    records: HashMap<Uuid, GameRecord>,
+    // このサーバーで行われた対局の、追加された順
+    order: VecDeque<Uuid>,
+    // 読み込まれた棋譜の、追加された順
+    imported_order: VecDeque<Uuid>,
+}
+
+impl GameArchive {
+    pub fn get(&self, game_id: Uuid) -> Option<&GameRecord> {
+        self.records.get(&game_id)
+    }
+
+    /// Adds a record. Imported records only push out older imported records, and games played
+    /// on this server are only pushed out once they have ended.
+    pub fn insert(&mut self, record: GameRecord) {
+        let game_id = record.game_id;
+        let is_imported = record.is_imported();
+        if self.records.insert(game_id, record).is_some() {
+            return;
+        }
+        if is_imported {
+            self.imported_order.push_back(game_id);
+            while self.imported_order.len() > MAX_IMPORTED_GAMES {
+                if let Some(game_id) = self.imported_order.pop_front() {
+                    self.records.remove(&game_id);
+                }
+            }
+            return;
+        }
+        self.order.push_back(game_id);
+        let mut excess = self.order.len().saturating_sub(MAX_ARCHIVED_GAMES);
+        if excess == 0 {
+            return;
+        }
+        // 進行中の対局にはまだ手が記録されるので、上限を超えていても残す
+        // 進行中の対局は部屋ごとに一つまでなので、残しても際限なく増えることはない
+        let records = &mut self.records;
+        self.order.retain(|game_id| {
+            if excess == 0 || records[game_id].finished_at.is_none() {
+                return true;
+            }
+            records.remove(game_id);
+            excess -= 1;
+            false
+        });
+    }
+
+    /// Returns every record, the games played on this server first, each oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &GameRecord> {
+        self.order
+            .iter()
+            .chain(self.imported_order.iter())
+            .filter_map(|game_id| self.records.get(game_id))
+    }
+}
+
+pub fn get_game_archive() -> &'static RwLock<GameArchive> {
+    static GAME_ARCHIVE: OnceLock<RwLock<GameArchive>> = OnceLock::new();
+    GAME_ARCHIVE.get_or_init(Default::default)
+}
+
+/// Starts a record for a game that has just begun, and returns its ID.
+pub fn begin_game(
+    room_id: Uuid,
+    board_size: usize,
+    board_style: GameSessionBoardStyle,
+    players: Vec<RecordedPlayer>,
+    first_turn: Side,
+) -> Uuid {
+    let game_id = Uuid::new_v4();
+    get_game_archive().write().insert(GameRecord {
+        game_id,
+        room_id,
+        board_size,
+        board_style,
+        players,
+        first_turn,
+        moves: Vec::new(),
+        winner: None,
+        started_at: get_unix_time(),
+        finished_at: None,
+    });
+    game_id
+}
+
+pub fn record_move(game_id: Uuid, recorded: RecordedMove) {
+    if let Some(record) = get_game_archive().write().records.get_mut(&game_id) {
+        record.moves.push(recorded);
+    }
+}
+
+pub fn finish_game(game_id: Uuid, winner: Side) {
+    if let Some(record) = get_game_archive().write().records.get_mut(&game_id) {
+        record.winner = Some(winner);
+        record.finished_at = Some(get_unix_time());
+    }
+}
+
+/// Marks a game that was stopped before either side won.
+pub fn abort_game(game_id: Uuid) {
+    if let Some(record) = get_game_archive().write().records.get_mut(&game_id) {
+        if record.finished_at.is_none() {
+            record.finished_at = Some(get_unix_time());
+        }
+    }
+}
@@ -1,33 +1,74 @@
 use std::net::SocketAddr;
 
 use axum::{
-    extract::{ConnectInfo, Path, Request, WebSocketUpgrade},
-    http::{header, StatusCode},
+    extract::{rejection::JsonRejection, ConnectInfo, Path, Query, Request, WebSocketUpgrade},
+    http::{header, HeaderMap, HeaderName, StatusCode},
     middleware::Next,
     response::{IntoResponse as _, Response},
     Json,
 };
 use uuid::Uuid;
 
-use crate::util::{
-    generate_name, log_ws, SimpleResponse, SimpleResponseWithHeaders, WebSocketAction,
+use crate::{
+    metrics,
+    util::{log_ws, shutdown, SimpleResponse, SimpleResponseWithHeaders, WebSocketAction},
 };
 
 use super::{
+    identity::{
+        create_token_cookie, get_token_from_headers, keep_identity, resolve_identity,
+        set_identity_name, validate_name,
+    },
+    lobby::{get_listing, notify_lobby},
     session::{
-        map::{get_game_session_map, get_immutable_session, get_mutable_session},
+        map::{get_room, try_insert_room},
         GameSession, GameSessionConfig, Side,
     },
-    structure::{CreateUserData, HttpPieceData, RoomData},
+    structure::{CreateUserData, HttpPieceData, JoinRequest, NewRoomQuery, ResultData, RoomData},
     ws::handle_socket,
 };
 
-pub async fn new_room() -> Response {
+const PRIVATE_ID_HEADER: HeaderName = HeaderName::from_static("x-private-id");
+
+#[inline(always)]
+pub(super) fn error_response(status_code: StatusCode, message: &'static str) -> Response {
+    SimpleResponse {
+        status_code,
+        content_type: "application/json",
+        content: Json(ResultData {
+            success: false,
+            message: Some(message),
+        }),
+    }
+    .into_response()
+}
+
+pub async fn new_room(Query(query): Query<NewRoomQuery>, headers: HeaderMap) -> Response {
+    // 部屋を作成したプレイヤーがホストになる
+    let (token, _) = resolve_identity(get_token_from_headers(&headers));
     let room_id = Uuid::new_v4();
-    get_game_session_map().write().insert(
-        room_id,
-        GameSession::new(room_id, GameSessionConfig::default()),
-    );
+    let mut config = GameSessionConfig::default();
+    if let Some(team_mode) = query.team_mode {
+        config.team_mode = team_mode;
+    }
+    if let Some(swap_sides) = query.swap_sides {
+        config.swap_sides_on_rematch = swap_sides;
+    }
+    if let Some(visibility) = query.visibility {
+        config.visibility = visibility;
+    }
+    if let Some(rated) = query.rated {
+        config.rated = rated;
+    }
+    let session = GameSession::new(room_id, config, Some(token));
+    let is_listed = get_listing(room_id, &session).is_some();
+    // 部屋ごとにタスクが動くので、上限を超えては作らない
+    if !try_insert_room(room_id, session) {
+        return error_response(StatusCode::SERVICE_UNAVAILABLE, "TOO_MANY_ROOMS");
+    }
+    if is_listed {
+        notify_lobby();
+    }
     let redirect_url = format!("/room/{}", room_id);
     let redirect_text = format!("Redirecting you to {}", &redirect_url);
     SimpleResponseWithHeaders {
@@ -36,102 +77,166 @@ pub async fn new_room() -> Response {
             content_type: "text/plain; charset=utf-8",
             content: redirect_text,
         },
-        headers: [(header::LOCATION, redirect_url)],
+        headers: [
+            (header::LOCATION, redirect_url),
+            (header::SET_COOKIE, create_token_cookie(token)),
+        ],
     }
     .into_response()
 }
 
 pub async fn room_existence_check(Path(room_id): Path<Uuid>, req: Request, next: Next) -> Response {
-    if get_game_session_map().read().get(&room_id).is_none() {
-        return SimpleResponse {
-            status_code: StatusCode::NOT_FOUND,
-            content_type: "text/plain; charset=utf-8",
-            content: "Invalid Room ID",
-        }
-        .into_response();
+    if get_room(room_id).is_none() {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID");
     }
     next.run(req).await
 }
 
-// これより下、部屋が存在するものとして処理を進めてOK
+// これより下、部屋が存在することは確認済みだが、
+// 管理者によって直後に閉じられることもあるので、get_roomで改めて取得し、返信がない場合にも備えること
 
-pub async fn room_data(Path(room_id): Path<Uuid>) -> Response {
-    let session = get_immutable_session(room_id);
+/// Builds the public view of the room, which leaves out private IDs and tokens.
+pub fn get_room_data(room_id: Uuid, session: &GameSession) -> RoomData {
     let piece_data = session.get_pieces();
+    RoomData {
+        room_id,
+        board_size: session.get_board_size(),
+        team_mode: session.get_team_mode(),
+        rated: session.get_config().rated,
+        game_id: session.get_game_id(),
+        phase: session.get_phase(),
+        winner: session.get_winner(),
+        host: session.get_host_public_id(),
+        is_locked: session.is_locked(),
+        current_turn: session.get_current_turn(),
+        current_turn_player: session
+            .get_turn_player()
+            .map(|private_id| session.get_public_id(private_id)),
+        top_players: session.get_player_data(Side::Top),
+        top_pieces: HttpPieceData::from_piece_data(piece_data, Side::Top),
+        bottom_players: session.get_player_data(Side::Bottom),
+        bottom_pieces: HttpPieceData::from_piece_data(piece_data, Side::Bottom),
+    }
+}
+
+pub async fn room_data(Path(room_id): Path<Uuid>) -> Response {
+    let room_data = match get_room(room_id) {
+        Some(room) => {
+            room.inspect(move |session| get_room_data(room_id, session))
+                .await
+        }
+        None => None,
+    };
+    let Some(room_data) = room_data else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID");
+    };
     SimpleResponse {
         status_code: StatusCode::OK,
         content_type: "application/json",
-        content: Json(RoomData {
-            room_id,
-            board_size: session.get_board_size(),
-            current_turn: session.get_current_turn(),
-            top_players: session.get_player_data(Side::Top),
-            top_pieces: HttpPieceData::from_piece_data(piece_data, Side::Top),
-            bottom_players: session.get_player_data(Side::Bottom),
-            bottom_pieces: HttpPieceData::from_piece_data(piece_data, Side::Bottom),
-        }),
+        content: Json(room_data),
     }
     .into_response()
 }
 
 #[inline(always)]
-fn try_create_player(room_id: Uuid, side: Side) -> Response {
-    let mut session = get_mutable_session(room_id);
-    let name = generate_name();
-    match session.create_player(side, &name) {
-        Some(private_id) => {
-            let public_id = session.get_public_id(private_id);
-            SimpleResponse {
-                status_code: StatusCode::OK,
-                content_type: "application/json",
-                content: Json(CreateUserData {
-                    success: true,
-                    message: None,
-                    side: Some(side),
-                    private_id: Some(private_id),
-                    public_id: Some(public_id),
-                    name: Some(name),
-                }),
-            }
-            .into_response()
+fn create_user_error(message: &'static str) -> SimpleResponse<Json<CreateUserData>> {
+    SimpleResponse {
+        status_code: StatusCode::BAD_REQUEST,
+        content_type: "application/json",
+        content: Json(CreateUserData {
+            success: false,
+            message: Some(message),
+            side: None,
+            private_id: None,
+            public_id: None,
+            name: None,
+        }),
+    }
+}
+
+async fn try_create_player(
+    room_id: Uuid,
+    side: Side,
+    requested_name: Option<String>,
+    headers: &HeaderMap,
+) -> Response {
+    let (token, identity) = resolve_identity(get_token_from_headers(headers));
+    let response = 'response: {
+        let requested_name = match requested_name.as_deref().map(validate_name) {
+            Some(Some(name)) => Some(name),
+            Some(None) => break 'response create_user_error("INVALID_NAME"),
+            None => None,
+        };
+        let Some(room) = get_room(room_id) else {
+            break 'response create_user_error("INVALID_ROOM_ID");
+        };
+        let player = match room
+            .join(side, token, identity, requested_name.clone())
+            .await
+        {
+            Some(Ok(player)) => player,
+            Some(Err(rejection)) => break 'response create_user_error(rejection.as_str()),
+            None => break 'response create_user_error("INVALID_ROOM_ID"),
+        };
+        keep_identity(token);
+        // 自分で選んだ名前だけを覚える。部屋の中で重なったために付いた名前は、その席だけのもの
+        if let Some(name) = requested_name.filter(|name| *name == player.name) {
+            set_identity_name(token, &name);
         }
-        None => SimpleResponse {
-            status_code: StatusCode::BAD_REQUEST,
+        SimpleResponse {
+            status_code: StatusCode::OK,
             content_type: "application/json",
             content: Json(CreateUserData {
-                success: false,
-                message: Some("PLAYER_LIMIT_EXCEEDED"),
-                side: None,
-                private_id: None,
-                public_id: None,
-                name: None,
+                success: true,
+                message: None,
+                side: Some(player.side),
+                private_id: Some(player.private_id),
+                public_id: Some(player.public_id),
+                name: Some(player.name),
             }),
         }
-        .into_response(),
+    };
+    SimpleResponseWithHeaders {
+        original_response: response,
+        headers: [(header::SET_COOKIE, create_token_cookie(token))],
     }
+    .into_response()
 }
 
-pub async fn join_top(Path(room_id): Path<Uuid>) -> Response {
-    try_create_player(room_id, Side::Top)
-}
-
-pub async fn join_bottom(Path(room_id): Path<Uuid>) -> Response {
-    try_create_player(room_id, Side::Bottom)
+pub async fn join(
+    Path(room_id): Path<Uuid>,
+    headers: HeaderMap,
+    body: Result<Json<JoinRequest>, JsonRejection>,
+) -> Response {
+    let Ok(Json(JoinRequest { side, name })) = body else {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_REQUEST_BODY");
+    };
+    try_create_player(room_id, side, name, &headers).await
 }
 
-pub async fn leave(Path((room_id, private_id)): Path<(Uuid, Uuid)>) -> Response {
-    if !get_mutable_session(room_id).remove_player(private_id) {
-        return SimpleResponse {
-            status_code: StatusCode::NOT_FOUND,
-            content_type: "text/plain; charset=utf-8",
-            content: "Invalid Player ID",
-        }
-        .into_response();
+pub async fn leave(Path(room_id): Path<Uuid>, headers: HeaderMap) -> Response {
+    let Some(private_id) = headers
+        .get(PRIVATE_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| Uuid::try_parse(value).ok())
+    else {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_PRIVATE_ID");
+    };
+    let Some(room) = get_room(room_id) else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID");
+    };
+    match room.leave(private_id).await {
+        Some(true) => {}
+        Some(false) => return error_response(StatusCode::NOT_FOUND, "INVALID_PLAYER_ID"),
+        None => return error_response(StatusCode::NOT_FOUND, "INVALID_ROOM_ID"),
     }
     SimpleResponse {
         status_code: StatusCode::OK,
-        content_type: "text/plain; charset=utf-8",
-        content: "Successful",
+        content_type: "application/json",
+        content: Json(ResultData {
+            success: true,
+            message: None,
+        }),
     }
     .into_response()
 }
@@ -141,10 +246,15 @@ pub async fn serve_ws(
     ws: WebSocketUpgrade,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> Response {
+    // レスポンスを返してからアップグレードされるまでの間も、シャットダウンで取りこぼさないように先に確保する
+    let task_guard = shutdown::track_task();
     ws.on_upgrade(move |socket| async move {
+        let _task_guard = task_guard;
         let ip = addr.ip();
         log_ws(ip, WebSocketAction::Connect);
+        metrics::WEBSOCKET_CONNECTIONS.inc();
         handle_socket(socket, ip, room_id).await;
+        metrics::WEBSOCKET_CONNECTIONS.dec();
         log_ws(ip, WebSocketAction::Disconnect);
     })
 }
@@ -0,0 +1,194 @@
+use std::{collections::HashMap, sync::OnceLock, time::Duration};
+
+use axum::http::{header, HeaderMap};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tokio::time::interval;
+use uuid::Uuid;
+
+use crate::util::{generate_name, get_unix_time, shutdown};
+
+const TOKEN_COOKIE_NAME: &str = "numbers_token";
+const TOKEN_COOKIE_MAX_AGE: u64 = 60 * 60 * 24 * 365;
+const MAX_NAME_LENGTH: usize = 16;
+// 部屋に座ったことのない識別情報を残しておく秒数
+const UNSEATED_IDENTITY_LIFETIME: u64 = 60 * 60 * 24;
+const IDENTITY_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+/// 部屋をまたいで同じプレイヤーを識別するための情報です。
+/// tokenは本人しか知り得ない値で、public_idとnameは他のプレイヤーにも公開されます。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Identity {
+    pub public_id: Uuid,
+    pub name: String,
+}
+
+/// An identity as kept by the server, along with how long it is kept.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredIdentity {
+    #[serde(flatten)]
+    pub identity: Identity,
+    /// UNIXエポックからの秒数。一度でも部屋に座った識別情報はNoneで、消えることはない
+    // この項目がない古いファイルから復元した識別情報は、座ったことがあるものとして扱う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+}
+
+pub fn get_identity_map() -> &'static RwLock<HashMap<Uuid, StoredIdentity>> {
+    static IDENTITY_MAP: OnceLock<RwLock<HashMap<Uuid, StoredIdentity>>> = OnceLock::new();
+    IDENTITY_MAP.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// Returns the identity bound to `token`, or mints a new token and identity
+/// if `token` is absent or unknown to this server.
+/// A minted identity expires after `UNSEATED_IDENTITY_LIFETIME` unless [`keep_identity`] is called.
+pub fn resolve_identity(token: Option<Uuid>) -> (Uuid, Identity) {
+    let mut identity_map = get_identity_map().write();
+    if let Some(token) = token {
+        if let Some(stored) = identity_map.get(&token) {
+            return (token, stored.identity.clone());
+        }
+    }
+    // クライアントが指定したtokenをそのまま採用すると、他人にtokenを仕込まれる恐れがあるため、
+    // 知らないtokenが送られてきた場合でも必ず新しく発行します。
+    let token = Uuid::new_v4();
+    let identity = Identity {
+        public_id: Uuid::new_v4(),
+        name: generate_name(),
+    };
+    identity_map.insert(
+        token,
+        StoredIdentity {
+            identity: identity.clone(),
+            expires_at: Some(get_unix_time() + UNSEATED_IDENTITY_LIFETIME),
+        },
+    );
+    (token, identity)
+}
+
+/// Keeps the identity bound to `token` for good. Call once it has taken a seat.
+pub fn keep_identity(token: Uuid) {
+    if let Some(stored) = get_identity_map().write().get_mut(&token) {
+        stored.expires_at = None;
+    }
+}
+
+pub fn set_identity_name(token: Uuid, name: &str) {
+    if let Some(stored) = get_identity_map().write().get_mut(&token) {
+        stored.identity.name = name.to_owned();
+    }
+}
+
+/// Starts the task that forgets identities that never took a seat. Call once at startup.
+pub fn spawn_identity_sweeper() {
+    tokio::spawn(async move {
+        let mut interval = interval(IDENTITY_SWEEP_INTERVAL);
+        let mut shutdown_rx = shutdown::subscribe();
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown::wait(&mut shutdown_rx) => break,
+            }
+            let now = get_unix_time();
+            get_identity_map()
+                .write()
+                .retain(|_, stored| stored.expires_at.is_none_or(|expires_at| expires_at > now));
+        }
+    });
+}
+
+/// Normalizes a player-chosen name, collapsing runs of spaces.
+/// Returns `None` if the result is empty, longer than `MAX_NAME_LENGTH` characters,
+/// or contains anything other than letters, numbers, spaces, `-`, `_` and `.`.
+pub fn validate_name(name: &str) -> Option<String> {
+    let name = name
+        .split(' ')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    let length = name.chars().count();
+    if length == 0 || length > MAX_NAME_LENGTH {
+        return None;
+    }
+    // 制御文字や記号、スペース以外の空白文字は、char::is_alphanumericで弾かれる
+    if !name
+        .chars()
+        .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_' | '.'))
+    {
+        return None;
+    }
+    Some(name)
+}
+
+pub fn get_token_from_headers(headers: &HeaderMap) -> Option<Uuid> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|cookies| cookies.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == TOKEN_COOKIE_NAME)
+        .and_then(|(_, value)| Uuid::try_parse(value).ok())
+}
+
+pub fn create_token_cookie(token: Uuid) -> String {
+    format!(
+        "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Lax",
+        TOKEN_COOKIE_NAME, token, TOKEN_COOKIE_MAX_AGE
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_normalized() {
+        assert_eq!(validate_name("alice").as_deref(), Some("alice"));
+        assert_eq!(
+            validate_name("  bob   the  3rd ").as_deref(),
+            Some("bob the 3rd")
+        );
+        assert_eq!(validate_name("a.b-c_d").as_deref(), Some("a.b-c_d"));
+        assert_eq!(validate_name("数字ゲーム").as_deref(), Some("数字ゲーム"));
+    }
+
+    #[test]
+    fn names_are_limited_by_characters_not_bytes() {
+        assert!(validate_name(&"a".repeat(MAX_NAME_LENGTH)).is_some());
+        assert!(validate_name(&"a".repeat(MAX_NAME_LENGTH + 1)).is_none());
+        assert!(validate_name(&"あ".repeat(MAX_NAME_LENGTH)).is_some());
+        // 詰めたスペースは数えない
+        let spaced = format!("{}    {}", "a".repeat(8), "b".repeat(7));
+        assert!(validate_name(&spaced).is_some());
+    }
+
+    #[test]
+    fn invalid_names_are_rejected() {
+        for name in ["", "   ", "<script>", "a\tb", "a\nb", "a\u{200b}b", "🙂"] {
+            assert!(validate_name(name).is_none(), "{:?} was accepted", name);
+        }
+    }
+
+    #[test]
+    fn unknown_tokens_get_a_new_identity() {
+        let (token, identity) = resolve_identity(None);
+        assert_eq!(
+            resolve_identity(Some(token)).1.public_id,
+            identity.public_id
+        );
+        let unknown = Uuid::new_v4();
+        let (new_token, new_identity) = resolve_identity(Some(unknown));
+        assert_ne!(new_token, unknown);
+        assert_ne!(new_identity.public_id, identity.public_id);
+    }
+
+    #[test]
+    fn identities_are_kept_once_seated() {
+        let (token, _) = resolve_identity(None);
+        let expires_at = |token| get_identity_map().read()[&token].expires_at;
+        assert!(expires_at(token).is_some_and(|expires_at| expires_at > get_unix_time()));
+        keep_identity(token);
+        assert_eq!(expires_at(token), None);
+    }
+}
@@ -0,0 +1,250 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, OnceLock},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        ConnectInfo, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse as _, Response},
+    Json,
+};
+use futures_util::future;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{
+    config, metrics,
+    util::{
+        log_ws,
+        rate_limit::{try_acquire_ws_message, TokenBucket},
+        shutdown, SimpleResponse, WebSocketAction, WebSocketSendAction,
+    },
+};
+
+use super::{
+    session::{map::get_rooms, GamePhase, GameSession, Side, Visibility},
+    structure::{LobbyEvent, PlayerCounts, PublicRoomData},
+};
+
+// 部屋の状態は短い間に何度も変わることが多いので、少し待ってからまとめて送る
+const LOBBY_UPDATE_DELAY: Duration = Duration::from_millis(250);
+
+/// The public rooms as of `built_at`, shared by every lobby socket.
+#[derive(Debug, Default)]
+struct LobbyListing {
+    rooms: HashMap<Uuid, PublicRoomData>,
+    built_at: Option<Instant>,
+}
+
+fn get_lobby_notifier() -> &'static watch::Sender<()> {
+    static LOBBY_NOTIFIER: OnceLock<watch::Sender<()>> = OnceLock::new();
+    LOBBY_NOTIFIER.get_or_init(|| watch::channel(()).0)
+}
+
+fn get_lobby_listing() -> &'static watch::Sender<Arc<LobbyListing>> {
+    static LOBBY_LISTING: OnceLock<watch::Sender<Arc<LobbyListing>>> = OnceLock::new();
+    LOBBY_LISTING.get_or_init(|| watch::channel(Arc::default()).0)
+}
+
+/// Tells the lobby that the room list has changed.
+/// Call only when a room is added, removed, or its [`get_listing`] changes.
+pub fn notify_lobby() {
+    get_lobby_notifier().send_replace(());
+}
+
+/// Returns how `session` appears in the room list, or `None` if it is not listed.
+pub fn get_listing(room_id: Uuid, session: &GameSession) -> Option<PublicRoomData> {
+    let config = session.get_config();
+    let seats = PlayerCounts {
+        top: session.get_player_data(Side::Top).len(),
+        bottom: session.get_player_data(Side::Bottom).len(),
+    };
+    let is_waiting = session.get_phase() == GamePhase::Lobby
+        && !session.is_locked()
+        && (seats.top < config.team_player_limit || seats.bottom < config.team_player_limit);
+    (config.visibility == Visibility::Public && is_waiting).then(|| PublicRoomData {
+        room_id,
+        board_size: config.board_size,
+        board_style: config.board_style,
+        team_mode: config.team_mode,
+        team_player_limit: config.team_player_limit,
+        rated: config.rated,
+        seats,
+        host_name: session.get_host_name().map(str::to_owned),
+        age_seconds: session.get_age().as_secs(),
+    })
+}
+
+/// Returns the public rooms that are still waiting for players.
+pub async fn list_public_rooms() -> Vec<PublicRoomData> {
+    let rooms = future::join_all(get_rooms().into_iter().map(|(room_id, room)| async move {
+        room.inspect(move |session| get_listing(room_id, session))
+            .await
+            .flatten()
+    }))
+    .await;
+    rooms.into_iter().flatten().collect()
+}
+
+/// Starts the task that rebuilds the room list for the lobby sockets. Call once at startup.
+pub fn spawn_lobby_publisher() {
+    tokio::spawn(async move {
+        let mut notifier_rx = get_lobby_notifier().subscribe();
+        // 最初の一覧は、変更を待たずに作る
+        notifier_rx.mark_changed();
+        let mut shutdown_rx = shutdown::subscribe();
+        loop {
+            tokio::select! {
+                result = notifier_rx.changed() => {
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                _ = shutdown::wait(&mut shutdown_rx) => break,
+            }
+            tokio::time::sleep(LOBBY_UPDATE_DELAY).await;
+            notifier_rx.borrow_and_update();
+            // 部屋を調べるのは、接続しているソケットの数によらず一回だけ
+            let rooms = list_public_rooms()
+                .await
+                .into_iter()
+                .map(|room| (room.room_id, room))
+                .collect();
+            get_lobby_listing().send_replace(Arc::new(LobbyListing {
+                rooms,
+                built_at: Some(Instant::now()),
+            }));
+        }
+    });
+}
+
+pub async fn rooms() -> Response {
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(list_public_rooms().await),
+    }
+    .into_response()
+}
+
+pub async fn serve_lobby_ws(
+    ws: WebSocketUpgrade,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    let task_guard = shutdown::track_task();
+    ws.on_upgrade(move |socket| async move {
+        let _task_guard = task_guard;
+        let ip = addr.ip();
+        log_ws(ip, WebSocketAction::Connect);
+        metrics::WEBSOCKET_CONNECTIONS.inc();
+        handle_lobby_socket(socket, ip).await;
+        metrics::WEBSOCKET_CONNECTIONS.dec();
+        log_ws(ip, WebSocketAction::Disconnect);
+    })
+}
+
+async fn send_event(socket: &mut WebSocket, ip: IpAddr, event: &LobbyEvent) -> bool {
+    let text = serde_json::to_string(event).unwrap();
+    if socket.send(Message::Text(text.clone())).await.is_err() {
+        log_ws(ip, Err(WebSocketSendAction::SendText(&text)));
+        return false;
+    }
+    log_ws(ip, Ok(WebSocketSendAction::SendText(&text)));
+    true
+}
+
+/// Sends the whole list once, then only the rooms that were added, changed or removed.
+async fn send_close(socket: &mut WebSocket, ip: IpAddr, cf: CloseFrame<'static>) {
+    if socket.send(Message::Close(Some(cf.clone()))).await.is_err() {
+        log_ws(ip, Err(WebSocketSendAction::SendClose(&cf)));
+    } else {
+        log_ws(ip, Ok(WebSocketSendAction::SendClose(&cf)));
+    }
+}
+
+async fn handle_lobby_socket(mut socket: WebSocket, ip: IpAddr) {
+    let mut listing_rx = get_lobby_listing().subscribe();
+    let mut shutdown_rx = shutdown::subscribe();
+    let mut token_bucket = TokenBucket::new(config::get().limit.ws_burst);
+    let mut listed = listing_rx.borrow_and_update().clone();
+    // 一覧は部屋が変わった時にしか作り直されないので、経過時間はその分を足す
+    let elapsed = listed
+        .built_at
+        .map_or(0, |built_at| built_at.elapsed().as_secs());
+    let rooms = listed
+        .rooms
+        .values()
+        .cloned()
+        .map(|room| PublicRoomData {
+            age_seconds: room.age_seconds + elapsed,
+            ..room
+        })
+        .collect();
+    if !send_event(&mut socket, ip, &LobbyEvent::List(rooms)).await {
+        return;
+    }
+    loop {
+        tokio::select! {
+            result = listing_rx.changed() => {
+                if result.is_err() {
+                    break;
+                }
+                let listing = listing_rx.borrow_and_update().clone();
+                let mut events = listed
+                    .rooms
+                    .keys()
+                    .filter(|room_id| !listing.rooms.contains_key(room_id))
+                    .map(|room_id| LobbyEvent::Remove(*room_id))
+                    .collect::<Vec<_>>();
+                events.extend(
+                    listing
+                        .rooms
+                        .values()
+                        .filter(|room| {
+                            listed
+                                .rooms
+                                .get(&room.room_id)
+                                .is_none_or(|listed_room| !listed_room.is_same_listing(room))
+                        })
+                        .cloned()
+                        .map(LobbyEvent::Update),
+                );
+                for event in events.iter() {
+                    if !send_event(&mut socket, ip, event).await {
+                        return;
+                    }
+                }
+                listed = listing;
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // 入力は使わないが、読むだけでも手間がかかるので、送りすぎる接続は切る
+                Some(Ok(_)) if !try_acquire_ws_message(&mut token_bucket) => {
+                    let cf = CloseFrame {
+                        code: close_code::POLICY,
+                        reason: Cow::from("Too Many Messages"),
+                    };
+                    send_close(&mut socket, ip, cf).await;
+                    break;
+                }
+                // ロビーではクライアントからの入力は使わない
+                Some(Ok(_)) => {}
+            },
+            _ = shutdown::wait(&mut shutdown_rx) => {
+                let cf = CloseFrame {
+                    code: close_code::RESTART,
+                    reason: Cow::from("Server Restarting"),
+                };
+                send_close(&mut socket, ip, cf).await;
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    collections::HashMap,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{rejection::JsonRejection, Path},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse as _, Response},
+    Json,
+};
+use parking_lot::Mutex;
+use tokio::{
+    sync::{watch, Notify},
+    time::interval,
+};
+use uuid::Uuid;
+
+use crate::{
+    config,
+    util::{generate_name, shutdown, SimpleResponse, SimpleResponseWithHeaders},
+};
+
+use super::{
+    http::error_response,
+    identity::{
+        create_token_cookie, get_token_from_headers, keep_identity, resolve_identity, Identity,
+    },
+    lobby::{get_listing, notify_lobby},
+    session::{
+        map::{has_room_capacity, try_insert_room},
+        GameSession, GameSessionConfig, Side,
+    },
+    structure::{MatchmakingJoinData, MatchmakingRequest, ResultData, TicketStatus},
+    MAX_BOARD_SIZE, MIN_BOARD_SIZE,
+};
+
+// 長すぎるとプロキシに切られることがあるので、それより短くしておく
+const LONG_POLL_TIMEOUT: Duration = Duration::from_secs(25);
+// マッチが成立した後、結果を受け取りに来るのを待つ時間
+const RESULT_RETENTION: Duration = Duration::from_secs(60);
+
+struct Ticket {
+    token: Uuid,
+    identity: Identity,
+    request: MatchmakingRequest,
+    enqueued_at: Instant,
+    finished_at: Option<Instant>,
+    status_tx: watch::Sender<TicketStatus>,
+}
+
+impl Ticket {
+    fn is_waiting(&self) -> bool {
+        matches!(*self.status_tx.borrow(), TicketStatus::Waiting)
+    }
+
+    fn finish(&mut self, status: TicketStatus) {
+        self.finished_at = Some(Instant::now());
+        self.status_tx.send_replace(status);
+    }
+}
+
+#[derive(Default)]
+struct MatchmakingQueue {
+    tickets: HashMap<Uuid, Ticket>,
+    // 先に並んだプレイヤーから順に組み合わせるため
+    waiting_order: Vec<Uuid>,
+}
+
+fn get_matchmaking_queue() -> &'static Mutex<MatchmakingQueue> {
+    static MATCHMAKING_QUEUE: OnceLock<Mutex<MatchmakingQueue>> = OnceLock::new();
+    MATCHMAKING_QUEUE.get_or_init(Default::default)
+}
+
+fn get_matchmaking_notify() -> &'static Notify {
+    static MATCHMAKING_NOTIFY: OnceLock<Notify> = OnceLock::new();
+    MATCHMAKING_NOTIFY.get_or_init(Notify::new)
+}
+
+/// Returns the settings both players can play with, or `None` if they disagree.
+fn merge_requests(a: MatchmakingRequest, b: MatchmakingRequest) -> Option<MatchmakingRequest> {
+    fn merge<T: PartialEq>(a: Option<T>, b: Option<T>) -> Option<Option<T>> {
+        match (a, b) {
+            (Some(a), Some(b)) if a != b => None,
+            (a, b) => Some(a.or(b)),
+        }
+    }
+    Some(MatchmakingRequest {
+        board_size: merge(a.board_size, b.board_size)?,
+        board_style: merge(a.board_style, b.board_style)?,
+        rated: merge(a.rated, b.rated)?,
+    })
+}
+
+/// Creates a room for two matched players and seats them on opposite sides.
+/// Returns `None` if there are already `limit.max_rooms` rooms.
+fn create_match_room(
+    request: MatchmakingRequest,
+    players: [(Uuid, &Identity); 2],
+) -> Option<[TicketStatus; 2]> {
+    let room_id = Uuid::new_v4();
+    let mut config = GameSessionConfig::default();
+    if let Some(board_size) = request.board_size {
+        config.board_size = board_size;
+    }
+    if let Some(board_style) = request.board_style {
+        config.board_style = board_style;
+    }
+    // 知らない相手との対局なので、どちらも希望しなければレーティング戦にする
+    config.rated = request.rated.unwrap_or(true);
+    // 自動で組まれた部屋にはホストを置かない
+    let mut session = GameSession::new(room_id, config, None);
+    let statuses = [Side::Bottom, Side::Top].map(|side| {
+        let (token, identity) = players[if side == Side::Bottom { 0 } else { 1 }];
+        let mut identity = identity.clone();
+        while session.is_name_taken(&identity.name) {
+            identity.name = generate_name();
+        }
+        // 空の部屋のそれぞれの側に一人ずつなので、人数制限にはかからない
+        let private_id = session.create_player(side, token, &identity).unwrap();
+        TicketStatus::Matched {
+            room_id,
+            side,
+            private_id,
+            public_id: session.get_public_id(private_id),
+        }
+    });
+    let is_listed = get_listing(room_id, &session).is_some();
+    if !try_insert_room(room_id, session) {
+        return None;
+    }
+    for (token, _) in players {
+        keep_identity(token);
+    }
+    if is_listed {
+        notify_lobby();
+    }
+    Some(statuses)
+}
+
+fn run_matchmaking() {
+    let mut queue = get_matchmaking_queue().lock();
+    let queue = &mut *queue;
+    let timeout = Duration::from_secs(config::get().matchmaking.timeout);
+    queue.tickets.retain(|_, ticket| {
+        ticket
+            .finished_at
+            .is_none_or(|finished_at| finished_at.elapsed() < RESULT_RETENTION)
+    });
+    for ticket in queue.tickets.values_mut() {
+        if ticket.is_waiting() && ticket.enqueued_at.elapsed() >= timeout {
+            ticket.finish(TicketStatus::TimedOut);
+        }
+    }
+    queue.waiting_order.retain(|ticket_id| {
+        queue
+            .tickets
+            .get(ticket_id)
+            .is_some_and(|ticket| ticket.is_waiting())
+    });
+    let mut i = 0;
+    // 部屋を作れない間は組み合わせずに待たせ、空くかタイムアウトするまで並んだままにする
+    while i < queue.waiting_order.len() && has_room_capacity() {
+        let first = &queue.tickets[&queue.waiting_order[i]];
+        let partner = queue.waiting_order[i + 1..].iter().position(|ticket_id| {
+            let second = &queue.tickets[ticket_id];
+            first.token != second.token && merge_requests(first.request, second.request).is_some()
+        });
+        let Some(partner) = partner else {
+            i += 1;
+            continue;
+        };
+        let (first_id, second_id) = (queue.waiting_order[i], queue.waiting_order[i + 1 + partner]);
+        let (first, second) = (&queue.tickets[&first_id], &queue.tickets[&second_id]);
+        let request = merge_requests(first.request, second.request).unwrap();
+        // 確認した後に他の場所で部屋が作られ、上限に達していることもある
+        let Some([first_status, second_status]) = create_match_room(
+            request,
+            [
+                (first.token, &first.identity),
+                (second.token, &second.identity),
+            ],
+        ) else {
+            break;
+        };
+        queue.waiting_order.remove(i + 1 + partner);
+        queue.waiting_order.remove(i);
+        queue
+            .tickets
+            .get_mut(&first_id)
+            .unwrap()
+            .finish(first_status);
+        queue
+            .tickets
+            .get_mut(&second_id)
+            .unwrap()
+            .finish(second_status);
+    }
+}
+
+/// Starts the task that pairs waiting players. Call once at startup.
+pub fn spawn_matchmaker() {
+    tokio::spawn(async move {
+        // 新しく並んだプレイヤーがいなくても、タイムアウトを処理するために定期的に動かす
+        let mut interval = interval(Duration::from_secs(1));
+        let mut shutdown_rx = shutdown::subscribe();
+        loop {
+            tokio::select! {
+                _ = get_matchmaking_notify().notified() => {}
+                _ = interval.tick() => {}
+                _ = shutdown::wait(&mut shutdown_rx) => break,
+            }
+            run_matchmaking();
+        }
+    });
+}
+
+fn join_error(message: &'static str) -> Response {
+    SimpleResponse {
+        status_code: StatusCode::BAD_REQUEST,
+        content_type: "application/json",
+        content: Json(MatchmakingJoinData {
+            success: false,
+            message: Some(message),
+            ticket_id: None,
+        }),
+    }
+    .into_response()
+}
+
+pub async fn join(
+    headers: HeaderMap,
+    body: Result<Json<MatchmakingRequest>, JsonRejection>,
+) -> Response {
+    let Ok(Json(request)) = body else {
+        return join_error("INVALID_REQUEST_BODY");
+    };
+    if request
+        .board_size
+        .is_some_and(|board_size| !(MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(&board_size))
+    {
+        return join_error("INVALID_BOARD_SIZE");
+    }
+    let (token, identity) = resolve_identity(get_token_from_headers(&headers));
+    let ticket_id = Uuid::new_v4();
+    {
+        let mut queue = get_matchmaking_queue().lock();
+        // 同じプレイヤーが別のタブなどから並び直した場合は、古い方を取り消す
+        for ticket in queue.tickets.values_mut() {
+            if ticket.token == token && ticket.is_waiting() {
+                ticket.finish(TicketStatus::Cancelled);
+            }
+        }
+        queue.tickets.insert(
+            ticket_id,
+            Ticket {
+                token,
+                identity,
+                request,
+                enqueued_at: Instant::now(),
+                finished_at: None,
+                status_tx: watch::channel(TicketStatus::Waiting).0,
+            },
+        );
+        queue.waiting_order.push(ticket_id);
+    }
+    get_matchmaking_notify().notify_one();
+    SimpleResponseWithHeaders {
+        original_response: SimpleResponse {
+            status_code: StatusCode::OK,
+            content_type: "application/json",
+            content: Json(MatchmakingJoinData {
+                success: true,
+                message: None,
+                ticket_id: Some(ticket_id),
+            }),
+        },
+        headers: [(header::SET_COOKIE, create_token_cookie(token))],
+    }
+    .into_response()
+}
+
+/// Waits up to `LONG_POLL_TIMEOUT` for the ticket to leave the `waiting` status,
+/// then returns its status either way.
+pub async fn poll(Path(ticket_id): Path<Uuid>) -> Response {
+    let Some(mut status_rx) = get_matchmaking_queue()
+        .lock()
+        .tickets
+        .get(&ticket_id)
+        .map(|ticket| ticket.status_tx.subscribe())
+    else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_TICKET_ID");
+    };
+    let mut shutdown_rx = shutdown::subscribe();
+    tokio::select! {
+        _ = status_rx.wait_for(|status| !matches!(status, TicketStatus::Waiting)) => {}
+        _ = tokio::time::sleep(LONG_POLL_TIMEOUT) => {}
+        _ = shutdown::wait(&mut shutdown_rx) => {}
+    }
+    let status = status_rx.borrow().clone();
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(status),
+    }
+    .into_response()
+}
+
+pub async fn cancel(Path(ticket_id): Path<Uuid>) -> Response {
+    let mut queue = get_matchmaking_queue().lock();
+    match queue.tickets.get_mut(&ticket_id) {
+        Some(ticket) if ticket.is_waiting() => ticket.finish(TicketStatus::Cancelled),
+        // 既にマッチが成立していた場合は、作られた部屋から抜けてもらう
+        Some(_) => return error_response(StatusCode::CONFLICT, "ALREADY_FINISHED"),
+        None => return error_response(StatusCode::NOT_FOUND, "INVALID_TICKET_ID"),
+    }
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(ResultData {
+            success: true,
+            message: None,
+        }),
+    }
+    .into_response()
+}
@@ -0,0 +1,572 @@
+use std::fmt::{self, Write as _};
+
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{IntoResponse as _, Response},
+    Json,
+};
+use chrono::{DateTime, SecondsFormat};
+use uuid::Uuid;
+
+use crate::util::{get_unix_time, SimpleResponse, SimpleResponseWithHeaders};
+
+use super::{
+    archive::{get_game_archive, GameRecord, RecordedMove, RecordedPlayer},
+    http::error_response,
+    session::{
+        board::{self, MoveOutcome},
+        map::get_room,
+        GameSessionBoardStyle, MoveRejection, Position, Side,
+    },
+    structure::ImportGameData,
+    MAX_BOARD_SIZE, MIN_BOARD_SIZE, RULES_VERSION,
+};
+
+// 棋譜の形式についてはREADME.mdの「棋譜」を参照
+const GAME_NAME: &str = "numbers";
+
+#[derive(Debug)]
+pub enum NotationError {
+    InvalidHeader(usize),
+    MissingHeader(&'static str),
+    UnsupportedGame(String),
+    UnsupportedRules(String),
+    InvalidBoard,
+    InvalidMove(usize),
+    IllegalMove(usize, MoveRejection),
+    ResultMismatch,
+}
+
+impl NotationError {
+    /// The code sent to clients in `message`.
+    fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidHeader(_) | Self::MissingHeader(_) => "INVALID_HEADER",
+            Self::UnsupportedGame(_) => "UNSUPPORTED_GAME",
+            Self::UnsupportedRules(_) => "UNSUPPORTED_RULES",
+            Self::InvalidBoard => "INVALID_BOARD",
+            Self::InvalidMove(_) => "INVALID_MOVE",
+            Self::IllegalMove(..) => "ILLEGAL_MOVE",
+            Self::ResultMismatch => "RESULT_MISMATCH",
+        }
+    }
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHeader(line) => write!(f, "line {} is not a valid header", line),
+            Self::MissingHeader(key) => write!(f, "the {} header is missing", key),
+            Self::UnsupportedGame(game) => {
+                write!(f, "{} is not a record of {}", game, GAME_NAME)
+            }
+            Self::UnsupportedRules(version) => {
+                write!(
+                    f,
+                    "rules version {} is not supported (expected {})",
+                    version, RULES_VERSION
+                )
+            }
+            Self::InvalidBoard => write!(
+                f,
+                "the board size must be between {} and {}",
+                MIN_BOARD_SIZE, MAX_BOARD_SIZE
+            ),
+            Self::InvalidMove(ply) => write!(f, "move {} cannot be read", ply + 1),
+            Self::IllegalMove(ply, rejection) => {
+                write!(f, "move {} is illegal ({})", ply + 1, rejection.as_str())
+            }
+            Self::ResultMismatch => write!(f, "the result does not match the moves"),
+        }
+    }
+}
+
+fn format_time(unix_time: u64) -> String {
+    DateTime::from_timestamp(unix_time as i64, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_time(text: &str) -> Option<u64> {
+    let timestamp = DateTime::parse_from_rfc3339(text).ok()?.timestamp();
+    u64::try_from(timestamp).ok()
+}
+
+/// 列は左からa, b, c...、段は下から1, 2, 3...で表す
+fn format_square(board_size: usize, (x, y): Position) -> String {
+    format!("{}{}", (b'a' + x as u8) as char, board_size - y)
+}
+
+fn parse_square(board_size: usize, text: &str) -> Option<Position> {
+    let mut chars = text.chars();
+    let file = chars.next().filter(char::is_ascii_lowercase)?;
+    let rank = chars.as_str().parse::<usize>().ok()?;
+    let x = (file as u8 - b'a') as usize;
+    if x >= board_size || rank == 0 || rank > board_size {
+        return None;
+    }
+    Some((x, board_size - rank))
+}
+
+fn parse_move(board_size: usize, text: &str) -> Option<(Position, Position)> {
+    let (from, to) = text.split_once(['-', 'x'])?;
+    Some((
+        parse_square(board_size, from)?,
+        parse_square(board_size, to)?,
+    ))
+}
+
+// 決着していない対局は、打ち切られていればaborted、続いていれば*
+fn format_result(record: &GameRecord) -> &'static str {
+    match record.winner {
+        Some(winner) => winner.as_str(),
+        None if record.is_aborted() => "aborted",
+        None => "*",
+    }
+}
+
+fn side_names(record: &GameRecord, side: Side) -> String {
+    record
+        .players
+        .iter()
+        .filter(|player| player.side == side)
+        .map(|player| player.name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Writes `record` as a game record file.
+pub fn export(record: &GameRecord) -> String {
+    let mut out = String::new();
+    let mut write_header = |key: &str, value: &str| {
+        let _ = writeln!(out, "[{} \"{}\"]", key, value);
+    };
+    write_header("Game", GAME_NAME);
+    write_header("Rules", RULES_VERSION);
+    write_header("GameId", &record.game_id.to_string());
+    write_header("Date", &format_time(record.started_at));
+    if let Some(finished_at) = record.finished_at {
+        write_header("EndDate", &format_time(finished_at));
+    }
+    write_header("BoardSize", &record.board_size.to_string());
+    write_header(
+        "BoardStyle",
+        match record.board_style {
+            GameSessionBoardStyle::Checker => "checker",
+            GameSessionBoardStyle::Chess => "chess",
+        },
+    );
+    write_header("FirstTurn", record.first_turn.as_str());
+    write_header("Top", &side_names(record, Side::Top));
+    write_header("Bottom", &side_names(record, Side::Bottom));
+    write_header("Result", format_result(record));
+    out.push('\n');
+    // 連続して駒を取った手は、同じ手番として一行にまとめる
+    let mut turn = 0;
+    let mut previous_side = None;
+    for recorded in record.moves.iter() {
+        if previous_side != Some(recorded.side) {
+            if previous_side.is_some() {
+                out.push('\n');
+            }
+            turn += 1;
+            let _ = write!(out, "{}.", turn);
+            previous_side = Some(recorded.side);
+        }
+        let separator = if recorded.from.0.abs_diff(recorded.to.0) == 2 {
+            'x'
+        } else {
+            '-'
+        };
+        let _ = write!(
+            out,
+            " {}{}{}",
+            format_square(record.board_size, recorded.from),
+            separator,
+            format_square(record.board_size, recorded.to)
+        );
+    }
+    if previous_side.is_some() {
+        out.push('\n');
+    }
+    out
+}
+
+/// Reads a game record file, checking every move against the rules.
+/// The result gets a new game ID and belongs to no room.
+pub fn import(text: &str) -> Result<GameRecord, NotationError> {
+    let mut headers = Vec::new();
+    let mut move_texts = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        // ;から行末まではコメント
+        let line = line.split(';').next().unwrap_or_default().trim();
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(header) = line.strip_prefix('[') {
+            if !move_texts.is_empty() {
+                return Err(NotationError::InvalidHeader(i + 1));
+            }
+            let (key, value) = header
+                .strip_suffix("\"]")
+                .and_then(|header| header.split_once(" \""))
+                .ok_or(NotationError::InvalidHeader(i + 1))?;
+            headers.push((key, value));
+            continue;
+        }
+        move_texts.extend(
+            line.split_whitespace()
+                .filter(|token| !token.ends_with('.')),
+        );
+    }
+    let get_header = |key| {
+        headers
+            .iter()
+            .find(|(header_key, _)| *header_key == key)
+            .map(|(_, value)| *value)
+    };
+    let require_header = |key| get_header(key).ok_or(NotationError::MissingHeader(key));
+    let game = require_header("Game")?;
+    if game != GAME_NAME {
+        return Err(NotationError::UnsupportedGame(game.to_owned()));
+    }
+    let rules = require_header("Rules")?;
+    if rules != RULES_VERSION {
+        return Err(NotationError::UnsupportedRules(rules.to_owned()));
+    }
+    let board_size = require_header("BoardSize")?
+        .parse::<usize>()
+        .ok()
+        .filter(|board_size| (MIN_BOARD_SIZE..=MAX_BOARD_SIZE).contains(board_size))
+        .ok_or(NotationError::InvalidBoard)?;
+    let board_style = match require_header("BoardStyle")? {
+        "checker" => GameSessionBoardStyle::Checker,
+        "chess" => GameSessionBoardStyle::Chess,
+        _ => return Err(NotationError::InvalidBoard),
+    };
+    let first_turn = match require_header("FirstTurn")? {
+        "top" => Side::Top,
+        "bottom" => Side::Bottom,
+        _ => return Err(NotationError::MissingHeader("FirstTurn")),
+    };
+    let mut players = Vec::new();
+    for side in [Side::Top, Side::Bottom] {
+        let key = if side == Side::Top { "Top" } else { "Bottom" };
+        for name in get_header(key).unwrap_or_default().split(", ") {
+            if !name.is_empty() {
+                players.push(RecordedPlayer {
+                    public_id: Uuid::nil(),
+                    name: name.to_owned(),
+                    side,
+                });
+            }
+        }
+    }
+    let mut pieces = board::create_initial_pieces(board_size, board_style);
+    let mut current_turn = first_turn;
+    let mut moves = Vec::new();
+    for (ply, move_text) in move_texts.into_iter().enumerate() {
+        // 決着した後の手は、対局中のサーバーと同じく受け付けない
+        if board::judge_winner(&pieces, current_turn).is_some() {
+            return Err(NotationError::IllegalMove(ply, MoveRejection::NotPlaying));
+        }
+        let (from, to) =
+            parse_move(board_size, move_text).ok_or(NotationError::InvalidMove(ply))?;
+        let outcome = board::apply_move(&mut pieces, current_turn, from, to)
+            .map_err(|rejection| NotationError::IllegalMove(ply, rejection))?;
+        moves.push(RecordedMove {
+            side: current_turn,
+            public_id: Uuid::nil(),
+            from,
+            to,
+        });
+        if outcome == MoveOutcome::TurnFinished {
+            current_turn = current_turn.opposite();
+        }
+    }
+    let winner = board::judge_winner(&pieces, current_turn);
+    let result = get_header("Result").unwrap_or("*");
+    let is_result_consistent = match result {
+        "*" => true,
+        "aborted" => winner.is_none(),
+        result => winner.is_some_and(|winner| winner.as_str() == result),
+    };
+    if !is_result_consistent {
+        return Err(NotationError::ResultMismatch);
+    }
+    let started_at = get_header("Date")
+        .and_then(parse_time)
+        .unwrap_or_else(get_unix_time);
+    let mut finished_at = get_header("EndDate").and_then(parse_time);
+    if result == "aborted" {
+        finished_at = finished_at.or(Some(started_at));
+    }
+    Ok(GameRecord {
+        game_id: Uuid::new_v4(),
+        room_id: Uuid::nil(),
+        board_size,
+        board_style,
+        players,
+        first_turn,
+        moves,
+        winner,
+        started_at,
+        finished_at,
+    })
+}
+
+fn export_response(game_id: Uuid) -> Response {
+    let Some(text) = get_game_archive().read().get(game_id).map(export) else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_GAME_ID");
+    };
+    SimpleResponseWithHeaders {
+        original_response: SimpleResponse {
+            status_code: StatusCode::OK,
+            content_type: "text/plain; charset=utf-8",
+            content: text,
+        },
+        headers: [(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"numbers-{}.txt\"", game_id),
+        )],
+    }
+    .into_response()
+}
+
+/// Exports the current or last game of the room.
+pub async fn export_room(Path(room_id): Path<Uuid>) -> Response {
+    let game_id = match get_room(room_id) {
+        Some(room) => room
+            .inspect(|session| session.get_game_id())
+            .await
+            .flatten(),
+        None => None,
+    };
+    let Some(game_id) = game_id else {
+        return error_response(StatusCode::NOT_FOUND, "NO_GAME_PLAYED");
+    };
+    export_response(game_id)
+}
+
+pub async fn export_game(Path(game_id): Path<Uuid>) -> Response {
+    export_response(game_id)
+}
+
+pub async fn import_game(body: String) -> Response {
+    let record = match import(&body) {
+        Ok(record) => record,
+        Err(error) => {
+            return SimpleResponse {
+                status_code: StatusCode::BAD_REQUEST,
+                content_type: "application/json",
+                content: Json(ImportGameData {
+                    success: false,
+                    message: Some(error.code()),
+                    detail: Some(error.to_string()),
+                    game_id: None,
+                }),
+            }
+            .into_response();
+        }
+    };
+    let game_id = record.game_id;
+    get_game_archive().write().insert(record);
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(ImportGameData {
+            success: true,
+            message: None,
+            detail: None,
+            game_id: Some(game_id),
+        }),
+    }
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng as _, SeedableRng as _};
+
+    use super::*;
+
+    /// Plays random legal moves, for `plies` moves or until the game ends.
+    fn play_game(board_size: usize, plies: usize, seed: u64) -> GameRecord {
+        let board_style = GameSessionBoardStyle::Checker;
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut pieces = board::create_initial_pieces(board_size, board_style);
+        let mut current_turn = Side::Bottom;
+        let mut moves = Vec::new();
+        while moves.len() < plies && board::judge_winner(&pieces, current_turn).is_none() {
+            let mut legal_moves = Vec::new();
+            for (from_y, from_x) in
+                (0..board_size).flat_map(|y| (0..board_size).map(move |x| (y, x)))
+            {
+                for (dx, dy) in [
+                    (-1, -1),
+                    (1, -1),
+                    (-1, 1),
+                    (1, 1),
+                    (-2, -2),
+                    (2, -2),
+                    (-2, 2),
+                    (2, 2),
+                ] {
+                    let to = (
+                        from_x.wrapping_add_signed(dx),
+                        from_y.wrapping_add_signed(dy),
+                    );
+                    let mut next_pieces = pieces.clone();
+                    if let Ok(outcome) =
+                        board::apply_move(&mut next_pieces, current_turn, (from_x, from_y), to)
+                    {
+                        legal_moves.push(((from_x, from_y), to, outcome, next_pieces));
+                    }
+                }
+            }
+            let (from, to, outcome, next_pieces) =
+                legal_moves.swap_remove(rng.gen_range(0..legal_moves.len()));
+            pieces = next_pieces;
+            moves.push(RecordedMove {
+                side: current_turn,
+                public_id: Uuid::nil(),
+                from,
+                to,
+            });
+            if outcome == MoveOutcome::TurnFinished {
+                current_turn = current_turn.opposite();
+            }
+        }
+        GameRecord {
+            game_id: Uuid::new_v4(),
+            room_id: Uuid::new_v4(),
+            board_size,
+            board_style,
+            players: vec![
+                RecordedPlayer {
+                    public_id: Uuid::new_v4(),
+                    name: "alice".to_owned(),
+                    side: Side::Top,
+                },
+                RecordedPlayer {
+                    public_id: Uuid::new_v4(),
+                    name: "bob".to_owned(),
+                    side: Side::Bottom,
+                },
+                RecordedPlayer {
+                    public_id: Uuid::new_v4(),
+                    name: "carol".to_owned(),
+                    side: Side::Bottom,
+                },
+            ],
+            first_turn: Side::Bottom,
+            moves,
+            winner: board::judge_winner(&pieces, current_turn),
+            started_at: 1_700_000_000,
+            finished_at: None,
+        }
+    }
+
+    fn assert_same_game(imported: &GameRecord, record: &GameRecord) {
+        assert_eq!(imported.board_size, record.board_size);
+        assert_eq!(imported.board_style, record.board_style);
+        assert_eq!(imported.first_turn, record.first_turn);
+        assert_eq!(imported.winner, record.winner);
+        assert_eq!(imported.started_at, record.started_at);
+        assert_eq!(imported.finished_at, record.finished_at);
+        let names = |record: &GameRecord| {
+            record
+                .players
+                .iter()
+                .map(|player| (player.side, player.name.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(imported), names(record));
+        let moves = |record: &GameRecord| {
+            record
+                .moves
+                .iter()
+                .map(|recorded| (recorded.side, recorded.from, recorded.to))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(moves(imported), moves(record));
+    }
+
+    #[test]
+    fn exported_games_are_imported_as_the_same_game() {
+        for board_size in [MIN_BOARD_SIZE, 8, MAX_BOARD_SIZE] {
+            let record = play_game(board_size, 40, 0);
+            let imported = import(&export(&record)).unwrap();
+            assert!(imported.is_imported());
+            assert_same_game(&imported, &record);
+        }
+    }
+
+    #[test]
+    fn finished_and_aborted_games_keep_their_result() {
+        // 決着するまで、違う手順を試す
+        let mut record = (0..)
+            .map(|seed| play_game(8, 1000, seed))
+            .find(|record| record.winner.is_some())
+            .unwrap();
+        record.finished_at = Some(record.started_at + 600);
+        assert_same_game(&import(&export(&record)).unwrap(), &record);
+
+        let mut record = play_game(8, 10, 0);
+        record.finished_at = Some(record.started_at + 60);
+        let text = export(&record);
+        assert!(text.contains("[Result \"aborted\"]"));
+        let imported = import(&text).unwrap();
+        assert!(imported.is_aborted());
+        assert_same_game(&imported, &record);
+    }
+
+    #[test]
+    fn results_that_do_not_match_the_moves_are_rejected() {
+        let record = play_game(8, 10, 0);
+        let text = export(&record).replace("[Result \"*\"]", "[Result \"top\"]");
+        assert!(matches!(import(&text), Err(NotationError::ResultMismatch)));
+    }
+
+    #[test]
+    fn illegal_moves_are_rejected() {
+        let mut record = play_game(8, 10, 0);
+        // 駒を動かさない手
+        record.moves[0].to = record.moves[0].from;
+        let text = export(&record);
+        assert!(matches!(
+            import(&text),
+            Err(NotationError::IllegalMove(0, _))
+        ));
+    }
+
+    #[test]
+    fn moves_after_the_game_has_ended_are_rejected() {
+        let mut record = (0..)
+            .map(|seed| play_game(8, 1000, seed))
+            .find(|record| record.winner.is_some())
+            .unwrap();
+        let ply = record.moves.len();
+        record.moves.push(record.moves[ply - 1]);
+        assert!(matches!(
+            import(&export(&record)),
+            Err(NotationError::IllegalMove(n, MoveRejection::NotPlaying)) if n == ply
+        ));
+    }
+
+    #[test]
+    fn records_of_other_games_are_rejected() {
+        let text = export(&play_game(8, 10, 0));
+        let header = format!("[Game \"{}\"]\n", GAME_NAME);
+        assert!(text.contains(&header));
+        assert!(matches!(
+            import(&text.replace(&header, "")),
+            Err(NotationError::MissingHeader("Game"))
+        ));
+        assert!(matches!(
+            import(&text.replace(&header, "[Game \"chess\"]\n")),
+            Err(NotationError::UnsupportedGame(game)) if game == "chess"
+        ));
+    }
+}
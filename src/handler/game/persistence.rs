@@ -0,0 +1,71 @@
+use std::{collections::HashMap, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    archive::{get_game_archive, GameRecord},
+    identity::{get_identity_map, StoredIdentity},
+    rating::{get_profile_map, PlayerProfile},
+    session::{
+        map::{get_rooms, insert_room},
+        snapshot::GameSessionSnapshot,
+        GameSession,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ServerState {
+    identities: HashMap<Uuid, StoredIdentity>,
+    rooms: HashMap<Uuid, GameSessionSnapshot>,
+    // この項目がない古いファイルから復元した場合は、誰もレーティング戦をしていないものとして扱う
+    #[serde(default)]
+    profiles: HashMap<Uuid, PlayerProfile>,
+    // 古い順
+    #[serde(default)]
+    games: Vec<GameRecord>,
+}
+
+/// Writes every room, identity, player profile and game record to `path` as JSON.
+pub async fn save_state(path: &Path) -> io::Result<()> {
+    let mut rooms = HashMap::new();
+    for (room_id, room) in get_rooms() {
+        // 保存する前に閉じられた部屋は保存しない
+        if let Some(snapshot) = room.snapshot().await {
+            rooms.insert(room_id, snapshot);
+        }
+    }
+    let state = ServerState {
+        identities: get_identity_map().read().clone(),
+        rooms,
+        profiles: get_profile_map().read().clone(),
+        games: get_game_archive().read().iter().cloned().collect(),
+    };
+    // 書き込み中に落ちても元のファイルが壊れないように、一旦別のファイルに書き出す
+    let temporary_path = path.with_extension("tmp");
+    fs::write(&temporary_path, serde_json::to_vec(&state)?)?;
+    fs::rename(&temporary_path, path)
+}
+
+/// Restores the rooms, identities, player profiles and game records saved by `save_state`.
+/// Returns the number of restored rooms, or 0 if `path` does not exist.
+pub fn load_state(path: &Path) -> io::Result<usize> {
+    let state = match fs::read(path) {
+        Ok(content) => serde_json::from_slice::<ServerState>(&content)?,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(error) => return Err(error),
+    };
+    get_identity_map().write().extend(state.identities);
+    get_profile_map().write().extend(state.profiles);
+    {
+        let mut game_archive = get_game_archive().write();
+        for record in state.games {
+            game_archive.insert(record);
+        }
+    }
+    let room_count = state.rooms.len();
+    for (room_id, snapshot) in state.rooms {
+        insert_room(room_id, GameSession::from_snapshot(room_id, snapshot));
+    }
+    Ok(room_count)
+}
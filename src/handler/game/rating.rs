@@ -0,0 +1,314 @@
+use std::{collections::HashMap, sync::OnceLock};
+
+use axum::{
+    extract::{Path, Query},
+    http::StatusCode,
+    response::{IntoResponse as _, Response},
+    Json,
+};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::util::{get_unix_time, SimpleResponse};
+
+use super::{
+    http::error_response,
+    session::Side,
+    structure::{LeaderboardEntry, LeaderboardQuery, PlayerProfileData},
+};
+
+pub const INITIAL_RATING: f64 = 1500.0;
+// 最初のうちは実力がわからないので、大きく変動させて早く実力に近づける
+const PROVISIONAL_GAMES: u32 = 10;
+const PROVISIONAL_K_FACTOR: f64 = 40.0;
+const K_FACTOR: f64 = 20.0;
+const MAX_HISTORY_LENGTH: usize = 100;
+const DEFAULT_LEADERBOARD_LIMIT: usize = 50;
+const MAX_LEADERBOARD_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameResult {
+    Win,
+    Loss,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatingHistoryEntry {
+    pub room_id: Uuid,
+    /// UNIXエポックからの秒数
+    pub finished_at: u64,
+    pub side: Side,
+    pub result: GameResult,
+    pub opponents: Vec<Uuid>,
+    pub rating_before: f64,
+    pub rating_after: f64,
+}
+
+/// レーティング戦の開始時に席についていたプレイヤーです。
+/// 途中で抜けたプレイヤーも自分の側の勝敗で計算されるように、終了時ではなく開始時の顔ぶれで計算します。
+/// 側の全員が抜けた場合は、その側の負けとして対局が終わります。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatedPlayer {
+    pub public_id: Uuid,
+    pub name: String,
+    pub side: Side,
+}
+
+/// レーティング戦の成績です。IdentityのpublicIdをキーとして保存されます。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayerProfile {
+    /// 最後にレーティング戦を終えた時の名前
+    pub name: String,
+    pub rating: f64,
+    pub wins: u32,
+    pub losses: u32,
+    /// 新しいものが後ろ。`MAX_HISTORY_LENGTH`件を超えた古いものは捨てられます。
+    pub history: Vec<RatingHistoryEntry>,
+}
+
+impl PlayerProfile {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            rating: INITIAL_RATING,
+            wins: 0,
+            losses: 0,
+            history: Vec::new(),
+        }
+    }
+
+    fn games(&self) -> u32 {
+        self.wins + self.losses
+    }
+
+    fn k_factor(&self) -> f64 {
+        if self.games() < PROVISIONAL_GAMES {
+            PROVISIONAL_K_FACTOR
+        } else {
+            K_FACTOR
+        }
+    }
+}
+
+pub fn get_profile_map() -> &'static RwLock<HashMap<Uuid, PlayerProfile>> {
+    static PROFILE_MAP: OnceLock<RwLock<HashMap<Uuid, PlayerProfile>>> = OnceLock::new();
+    PROFILE_MAP.get_or_init(|| RwLock::new(HashMap::new()))
+}
+
+/// The chance that a team rated `rating` beats a team rated `opponent_rating`.
+fn expected_score(rating: f64, opponent_rating: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((opponent_rating - rating) / 400.0))
+}
+
+/// Updates the ratings of everyone who played a rated game that `winner` won.
+/// Teams are rated by the average rating of their players, and every player on a team
+/// moves by their own K-factor against the other team's average.
+/// Games with an empty side, or with the same player on both sides, are not rated.
+pub fn record_game(room_id: Uuid, winner: Side, lineup: &[RatedPlayer]) {
+    let (top, bottom): (Vec<_>, Vec<_>) =
+        lineup.iter().partition(|player| player.side == Side::Top);
+    if top.is_empty()
+        || bottom.is_empty()
+        || top
+            .iter()
+            .any(|top_player| bottom.iter().any(|p| p.public_id == top_player.public_id))
+    {
+        return;
+    }
+    let finished_at = get_unix_time();
+    let mut profile_map = get_profile_map().write();
+    let mut average_rating = |players: &[&RatedPlayer]| {
+        players
+            .iter()
+            .map(|player| {
+                profile_map
+                    .entry(player.public_id)
+                    .or_insert_with(|| PlayerProfile::new(player.name.clone()))
+                    .rating
+            })
+            .sum::<f64>()
+            / players.len() as f64
+    };
+    let top_rating = average_rating(&top);
+    let bottom_rating = average_rating(&bottom);
+    for (side, players, opponents, rating, opponent_rating) in [
+        (Side::Top, &top, &bottom, top_rating, bottom_rating),
+        (Side::Bottom, &bottom, &top, bottom_rating, top_rating),
+    ] {
+        let (result, score) = if side == winner {
+            (GameResult::Win, 1.0)
+        } else {
+            (GameResult::Loss, 0.0)
+        };
+        let change_ratio = score - expected_score(rating, opponent_rating);
+        let opponents = opponents
+            .iter()
+            .map(|opponent| opponent.public_id)
+            .collect::<Vec<_>>();
+        for player in players {
+            let profile = profile_map.get_mut(&player.public_id).unwrap();
+            let rating_before = profile.rating;
+            profile.rating += profile.k_factor() * change_ratio;
+            profile.name = player.name.clone();
+            match result {
+                GameResult::Win => profile.wins += 1,
+                GameResult::Loss => profile.losses += 1,
+            }
+            profile.history.push(RatingHistoryEntry {
+                room_id,
+                finished_at,
+                side,
+                result,
+                opponents: opponents.clone(),
+                rating_before,
+                rating_after: profile.rating,
+            });
+            if profile.history.len() > MAX_HISTORY_LENGTH {
+                profile.history.remove(0);
+            }
+        }
+    }
+}
+
+pub async fn player_profile(Path(public_id): Path<Uuid>) -> Response {
+    let Some(profile) = get_profile_map().read().get(&public_id).cloned() else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_PLAYER_ID");
+    };
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(PlayerProfileData {
+            public_id,
+            rating: profile.rating.round() as i64,
+            wins: profile.wins,
+            losses: profile.losses,
+            is_provisional: profile.games() < PROVISIONAL_GAMES,
+            name: profile.name,
+            history: profile.history,
+        }),
+    }
+    .into_response()
+}
+
+pub async fn leaderboard(Query(query): Query<LeaderboardQuery>) -> Response {
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_LEADERBOARD_LIMIT)
+        .min(MAX_LEADERBOARD_LIMIT);
+    let mut entries = get_profile_map()
+        .read()
+        .iter()
+        // 実力が定まっていないプレイヤーが、数回勝っただけで上位に来ないようにする
+        .filter(|(_, profile)| profile.games() >= PROVISIONAL_GAMES)
+        .map(|(public_id, profile)| LeaderboardEntry {
+            rank: 0,
+            public_id: *public_id,
+            name: profile.name.clone(),
+            rating: profile.rating.round() as i64,
+            wins: profile.wins,
+            losses: profile.losses,
+        })
+        .collect::<Vec<_>>();
+    entries.sort_by(|a, b| b.rating.cmp(&a.rating).then(b.wins.cmp(&a.wins)));
+    entries.truncate(limit);
+    for (i, entry) in entries.iter_mut().enumerate() {
+        entry.rank = i + 1;
+    }
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(entries),
+    }
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn player(side: Side) -> RatedPlayer {
+        RatedPlayer {
+            public_id: Uuid::new_v4(),
+            name: "player".to_owned(),
+            side,
+        }
+    }
+
+    fn get_profile(player: &RatedPlayer) -> Option<PlayerProfile> {
+        get_profile_map().read().get(&player.public_id).cloned()
+    }
+
+    #[test]
+    fn equal_players_move_by_half_the_k_factor() {
+        let (top, bottom) = (player(Side::Top), player(Side::Bottom));
+        record_game(Uuid::new_v4(), Side::Bottom, &[top.clone(), bottom.clone()]);
+        let winner = get_profile(&bottom).unwrap();
+        let loser = get_profile(&top).unwrap();
+        assert_eq!(winner.rating, INITIAL_RATING + PROVISIONAL_K_FACTOR / 2.0);
+        assert_eq!(loser.rating, INITIAL_RATING - PROVISIONAL_K_FACTOR / 2.0);
+        assert_eq!((winner.wins, winner.losses), (1, 0));
+        assert_eq!((loser.wins, loser.losses), (0, 1));
+        assert_eq!(winner.history[0].result, GameResult::Win);
+        assert_eq!(winner.history[0].opponents, vec![top.public_id]);
+        assert_eq!(winner.history[0].rating_before, INITIAL_RATING);
+    }
+
+    #[test]
+    fn the_k_factor_drops_after_the_provisional_games() {
+        let (top, bottom) = (player(Side::Top), player(Side::Bottom));
+        for i in 0..PROVISIONAL_GAMES {
+            let winner = if i % 2 == 0 { Side::Top } else { Side::Bottom };
+            record_game(Uuid::new_v4(), winner, &[top.clone(), bottom.clone()]);
+        }
+        let rating_before = get_profile(&top).unwrap().rating;
+        let expected = expected_score(rating_before, get_profile(&bottom).unwrap().rating);
+        record_game(Uuid::new_v4(), Side::Top, &[top.clone(), bottom.clone()]);
+        assert_eq!(
+            get_profile(&top).unwrap().rating,
+            rating_before + K_FACTOR * (1.0 - expected)
+        );
+    }
+
+    #[test]
+    fn teams_are_rated_by_their_average() {
+        let top = [player(Side::Top), player(Side::Top)];
+        let bottom = player(Side::Bottom);
+        // 片方だけ先に勝たせて、チームの中でレーティングに差をつける
+        record_game(
+            Uuid::new_v4(),
+            Side::Top,
+            &[top[0].clone(), player(Side::Bottom)],
+        );
+        let strong_rating = get_profile(&top[0]).unwrap().rating;
+        let lineup = [top[0].clone(), top[1].clone(), bottom.clone()];
+        record_game(Uuid::new_v4(), Side::Bottom, &lineup);
+        let average = (strong_rating + INITIAL_RATING) / 2.0;
+        let change = PROVISIONAL_K_FACTOR * (1.0 - expected_score(INITIAL_RATING, average));
+        assert_eq!(
+            get_profile(&bottom).unwrap().rating,
+            INITIAL_RATING + change
+        );
+        // どちらも同じだけ下がる
+        assert_eq!(get_profile(&top[0]).unwrap().rating, strong_rating - change);
+        assert_eq!(
+            get_profile(&top[1]).unwrap().rating,
+            INITIAL_RATING - change
+        );
+    }
+
+    #[test]
+    fn games_without_two_sides_are_not_rated() {
+        let top = player(Side::Top);
+        record_game(Uuid::new_v4(), Side::Top, std::slice::from_ref(&top));
+        assert!(get_profile(&top).is_none());
+        // 同じプレイヤーが両方の側にいる
+        let bottom = RatedPlayer {
+            side: Side::Bottom,
+            ..top.clone()
+        };
+        record_game(Uuid::new_v4(), Side::Top, &[top.clone(), bottom]);
+        assert!(get_profile(&top).is_none());
+    }
+}
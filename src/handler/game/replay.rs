@@ -0,0 +1,312 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+};
+
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket},
+        ConnectInfo, Path, Query, WebSocketUpgrade,
+    },
+    http::StatusCode,
+    response::{IntoResponse as _, Response},
+    Json,
+};
+use parking_lot::Mutex;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::{
+    config, metrics,
+    util::{
+        log_error, log_ws,
+        rate_limit::{try_acquire_ws_message, TokenBucket},
+        shutdown, SimpleResponse, WebSocketAction, WebSocketReceiveAction, WebSocketSendAction,
+    },
+};
+
+use super::{
+    archive::{get_game_archive, GameRecord},
+    http::error_response,
+    session::{board, map::get_room, GamePhase, Side},
+    structure::{
+        HttpPieceData, PositionData, PositionQuery, ReplayAction, ReplayData, ReplayEvent,
+    },
+};
+
+/// The position everyone watching a game sees, and the only connection allowed to move it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ReplayCursor {
+    ply: usize,
+    controller: Uuid,
+}
+
+#[derive(Debug)]
+struct ReplayViewers {
+    cursor_tx: watch::Sender<ReplayCursor>,
+    /// 接続した順。先頭の接続が操作権を持つ
+    connection_ids: Vec<Uuid>,
+}
+
+fn get_replay_viewers() -> &'static Mutex<HashMap<Uuid, ReplayViewers>> {
+    static REPLAY_VIEWERS: OnceLock<Mutex<HashMap<Uuid, ReplayViewers>>> = OnceLock::new();
+    REPLAY_VIEWERS.get_or_init(Default::default)
+}
+
+fn add_viewer(game_id: Uuid, connection_id: Uuid) -> watch::Receiver<ReplayCursor> {
+    let mut replay_viewers = get_replay_viewers().lock();
+    let viewers = replay_viewers
+        .entry(game_id)
+        .or_insert_with(|| ReplayViewers {
+            cursor_tx: watch::channel(ReplayCursor {
+                ply: 0,
+                controller: connection_id,
+            })
+            .0,
+            connection_ids: Vec::new(),
+        });
+    viewers.connection_ids.push(connection_id);
+    viewers.cursor_tx.subscribe()
+}
+
+// 操作していた接続が抜けた場合は、次に古い接続に操作権を渡す
+fn remove_viewer(game_id: Uuid, connection_id: Uuid) {
+    let mut replay_viewers = get_replay_viewers().lock();
+    let Some(viewers) = replay_viewers.get_mut(&game_id) else {
+        return;
+    };
+    viewers.connection_ids.retain(|id| *id != connection_id);
+    let Some(&next_controller) = viewers.connection_ids.first() else {
+        replay_viewers.remove(&game_id);
+        return;
+    };
+    viewers.cursor_tx.send_if_modified(|cursor| {
+        let is_controller_changed = cursor.controller == connection_id;
+        if is_controller_changed {
+            cursor.controller = next_controller;
+        }
+        is_controller_changed
+    });
+}
+
+fn get_record(game_id: Uuid) -> Option<GameRecord> {
+    get_game_archive().read().get(game_id).cloned()
+}
+
+async fn is_in_progress(record: &GameRecord) -> bool {
+    if record.finished_at.is_some() {
+        return false;
+    }
+    let Some(room) = get_room(record.room_id) else {
+        return false;
+    };
+    let game_id = record.game_id;
+    room.inspect(move |session| {
+        session.get_game_id() == Some(game_id) && session.get_phase() == GamePhase::Playing
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Computes the board after `ply` moves, or `None` if the record cannot be replayed that far.
+fn get_position_data(record: &GameRecord, ply: usize) -> Option<PositionData> {
+    if ply > record.moves.len() {
+        return None;
+    }
+    let position = match record.position_at(ply) {
+        Ok(position) => position,
+        Err(error) => {
+            // このサーバーが記録した手なので、ここに来るのはルールの変更などで再現できなくなった場合だけ
+            log_error!("replay", error);
+            return None;
+        }
+    };
+    Some(PositionData {
+        game_id: record.game_id,
+        ply,
+        total_plies: record.moves.len(),
+        current_turn: position.current_turn,
+        last_move: ply.checked_sub(1).map(|i| record.moves[i]),
+        winner: board::judge_winner(&position.pieces, position.current_turn),
+        top_pieces: HttpPieceData::from_piece_data(&position.pieces, Side::Top),
+        bottom_pieces: HttpPieceData::from_piece_data(&position.pieces, Side::Bottom),
+    })
+}
+
+pub async fn replay(Path(game_id): Path<Uuid>) -> Response {
+    let Some(record) = get_record(game_id) else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_GAME_ID");
+    };
+    let initial_pieces = record.initial_position().pieces;
+    let in_progress = is_in_progress(&record).await;
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(ReplayData {
+            initial_top_pieces: HttpPieceData::from_piece_data(&initial_pieces, Side::Top),
+            initial_bottom_pieces: HttpPieceData::from_piece_data(&initial_pieces, Side::Bottom),
+            in_progress,
+            record,
+        }),
+    }
+    .into_response()
+}
+
+pub async fn position(Path(game_id): Path<Uuid>, Query(query): Query<PositionQuery>) -> Response {
+    let Some(record) = get_record(game_id) else {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_GAME_ID");
+    };
+    let ply = query.ply.unwrap_or(record.moves.len());
+    let Some(position) = get_position_data(&record, ply) else {
+        return error_response(StatusCode::BAD_REQUEST, "INVALID_PLY");
+    };
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "application/json",
+        content: Json(position),
+    }
+    .into_response()
+}
+
+/// Opens a view of a game. Everyone watching the same game shares one cursor,
+/// which only the oldest connection can move; the others follow it read-only.
+pub async fn serve_replay_ws(
+    ws: WebSocketUpgrade,
+    Path(game_id): Path<Uuid>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+) -> Response {
+    if get_record(game_id).is_none() {
+        return error_response(StatusCode::NOT_FOUND, "INVALID_GAME_ID");
+    }
+    let task_guard = shutdown::track_task();
+    ws.on_upgrade(move |socket| async move {
+        let _task_guard = task_guard;
+        let ip = addr.ip();
+        log_ws(ip, WebSocketAction::Connect);
+        metrics::WEBSOCKET_CONNECTIONS.inc();
+        let connection_id = Uuid::new_v4();
+        let cursor_rx = add_viewer(game_id, connection_id);
+        handle_replay_socket(socket, ip, game_id, connection_id, cursor_rx).await;
+        remove_viewer(game_id, connection_id);
+        metrics::WEBSOCKET_CONNECTIONS.dec();
+        log_ws(ip, WebSocketAction::Disconnect);
+    })
+}
+
+async fn send_event(socket: &mut WebSocket, ip: IpAddr, event: &ReplayEvent) -> bool {
+    let text = serde_json::to_string(event).unwrap();
+    if socket.send(Message::Text(text.clone())).await.is_err() {
+        log_ws(ip, Err(WebSocketSendAction::SendText(&text)));
+        return false;
+    }
+    log_ws(ip, Ok(WebSocketSendAction::SendText(&text)));
+    true
+}
+
+async fn send_close(socket: &mut WebSocket, ip: IpAddr, cf: CloseFrame<'static>) {
+    if socket.send(Message::Close(Some(cf.clone()))).await.is_err() {
+        log_ws(ip, Err(WebSocketSendAction::SendClose(&cf)));
+    } else {
+        log_ws(ip, Ok(WebSocketSendAction::SendClose(&cf)));
+    }
+}
+
+async fn send_position(socket: &mut WebSocket, ip: IpAddr, game_id: Uuid, ply: usize) -> bool {
+    // 対局中の記録は手が増えていくので、毎回読み直す
+    let Some(position) = get_record(game_id).and_then(|record| get_position_data(&record, ply))
+    else {
+        return false;
+    };
+    send_event(socket, ip, &ReplayEvent::Position(position)).await
+}
+
+async fn handle_replay_socket(
+    mut socket: WebSocket,
+    ip: IpAddr,
+    game_id: Uuid,
+    connection_id: Uuid,
+    mut cursor_rx: watch::Receiver<ReplayCursor>,
+) {
+    let mut shutdown_rx = shutdown::subscribe();
+    let mut token_bucket = TokenBucket::new(config::get().limit.ws_burst);
+    let mut cursor = *cursor_rx.borrow_and_update();
+    let is_controller = cursor.controller == connection_id;
+    if !send_position(&mut socket, ip, game_id, cursor.ply).await
+        || !send_event(&mut socket, ip, &ReplayEvent::Control(is_controller)).await
+    {
+        return;
+    }
+    loop {
+        tokio::select! {
+            result = cursor_rx.changed() => {
+                if result.is_err() {
+                    break;
+                }
+                let new_cursor = *cursor_rx.borrow_and_update();
+                if new_cursor.ply != cursor.ply
+                    && !send_position(&mut socket, ip, game_id, new_cursor.ply).await
+                {
+                    break;
+                }
+                let is_controller = new_cursor.controller == connection_id;
+                if is_controller != (cursor.controller == connection_id)
+                    && !send_event(&mut socket, ip, &ReplayEvent::Control(is_controller)).await
+                {
+                    break;
+                }
+                cursor = new_cursor;
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // 操作のたびに全員へ局面を送るので、送りすぎる接続は切る
+                Some(Ok(_)) if !try_acquire_ws_message(&mut token_bucket) => {
+                    let cf = CloseFrame {
+                        code: close_code::POLICY,
+                        reason: Cow::from("Too Many Messages"),
+                    };
+                    send_close(&mut socket, ip, cf).await;
+                    break;
+                }
+                Some(Ok(Message::Text(text))) => {
+                    log_ws(ip, WebSocketReceiveAction::GotText(&text));
+                    let Ok(action) = serde_json::from_str::<ReplayAction>(&text) else {
+                        continue;
+                    };
+                    let Some(total_plies) = get_record(game_id).map(|record| record.moves.len())
+                    else {
+                        break;
+                    };
+                    let replay_viewers = get_replay_viewers().lock();
+                    let Some(viewers) = replay_viewers.get(&game_id) else {
+                        break;
+                    };
+                    // 操作権を持たない接続からの操作は無視する
+                    viewers.cursor_tx.send_if_modified(|cursor| {
+                        if cursor.controller != connection_id {
+                            return false;
+                        }
+                        cursor.ply = match action {
+                            ReplayAction::Next => cursor.ply.saturating_add(1),
+                            ReplayAction::Previous => cursor.ply.saturating_sub(1),
+                            ReplayAction::Seek(new_ply) => new_ply,
+                        }
+                        .min(total_plies);
+                        true
+                    });
+                }
+                // 再生用の接続では、テキスト以外の入力は使わない
+                Some(Ok(_)) => {}
+            },
+            _ = shutdown::wait(&mut shutdown_rx) => {
+                let cf = CloseFrame {
+                    code: close_code::RESTART,
+                    reason: Cow::from("Server Restarting"),
+                };
+                send_close(&mut socket, ip, cf).await;
+                break;
+            }
+        }
+    }
+}
@@ -1,41 +1,60 @@
 use std::{
-    collections::HashMap,
-    time::{Duration, Instant},
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant, SystemTime},
 };
 
-use serde::Serialize;
-use tokio::{
-    sync::{broadcast, oneshot},
-    time::interval,
-};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, oneshot};
 use uuid::Uuid;
 
-use crate::handler::game::{PLAYER_INACTIVE_THRESHOLD, PLAYER_KICK_THRESHOLD};
+use crate::{config, metrics, util::generate_name};
 
 use super::{
+    archive::{self, RecordedMove, RecordedPlayer},
+    identity::Identity,
+    rating::{self, RatedPlayer},
     structure::{RoomEvent, RoomEventWithId},
-    DEFAULT_BOARD_SIZE, DEFAULT_TEAM_PLAYER_LIMIT, INITIAL_NUMBER, QUEUE_MESSAGE_LIMIT,
 };
 
+use self::heartbeat::PlayerCheck;
+
+pub mod actor;
+pub mod board;
+pub mod heartbeat;
 pub mod map;
+pub mod snapshot;
 
 pub type Position = (usize, usize);
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Side {
     Top,
     Bottom,
 }
 
+impl Side {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Top => "top",
+            Self::Bottom => "bottom",
+        }
+    }
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Self::Top => Self::Bottom,
+            Self::Bottom => Self::Top,
+        }
+    }
+}
+
 impl Serialize for Side {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        match *self {
-            Self::Top => serializer.serialize_str("top"),
-            Self::Bottom => serializer.serialize_str("bottom"),
-        }
+        serializer.serialize_str(self.as_str())
     }
 }
 
@@ -45,60 +64,30 @@ pub struct PlayerData {
     pub name: String,
     pub selecting_piece: Option<Position>,
     pub is_inactive: bool,
+    pub is_ready: bool,
+    // 最後の接続が閉じてから、まだ戻ってきていない
+    pub is_disconnected: bool,
     #[serde(skip)]
     pub side: Side,
     #[serde(skip)]
     pub last_heartbeat: Instant,
+    // 最後の接続が閉じた時刻。接続が一つでもあればNone
+    #[serde(skip)]
+    pub disconnected_at: Option<Instant>,
+    #[serde(skip)]
+    pub token: Uuid,
+    #[serde(skip)]
+    pub join_order: u64,
 }
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct PieceData {
     pub side: Side,
     pub number: u8,
 }
 
-#[derive(Debug)]
-struct HeartbeatTimer {
-    tx: Option<oneshot::Sender<()>>,
-}
-
-impl HeartbeatTimer {
-    fn new(room_id: Uuid) -> Self {
-        let (tx, mut rx) = oneshot::channel();
-        tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(1));
-            loop {
-                tokio::select! {
-                    _ = interval.tick() => {
-                        let mut session = map::get_mutable_session(room_id);
-                        for private_id in session.get_player_ids() {
-                            let player = session.get_player_mut(private_id).unwrap();
-                            if player.last_heartbeat.elapsed().as_secs() > PLAYER_KICK_THRESHOLD {
-                                session.remove_player(private_id);
-                            } else if player.last_heartbeat.elapsed().as_secs() > PLAYER_INACTIVE_THRESHOLD {
-                                player.is_inactive = true;
-                            } else if player.is_inactive {
-                                player.is_inactive = false;
-                            }
-                        }
-                    }
-                    _ = &mut rx => break,
-                }
-            }
-        });
-        Self { tx: Some(tx) }
-    }
-}
-
-impl Drop for HeartbeatTimer {
-    fn drop(&mut self) {
-        if let Some(tx) = self.tx.take() {
-            let _ = tx.send(());
-        }
-    }
-}
-
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum GameSessionBoardStyle {
     // x x x x x
     //  x x x x
@@ -110,86 +99,178 @@ pub enum GameSessionBoardStyle {
     Chess,
 }
 
-#[derive(Debug, Clone, Copy)]
+/// 同じ側に複数のプレイヤーがいる場合に、誰が駒を動かせるかを決めます。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TeamMode {
+    // 自分の側の手番であれば誰でも動かせる
+    #[default]
+    FreeForAll,
+    // 参加した順に交代で動かす
+    Rotation,
+    // 最初に参加したプレイヤー(キャプテン)だけが動かせる
+    Captain,
+}
+
+/// 部屋を他のプレイヤーがどうやって見つけられるかを決めます。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    // 部屋の一覧に表示される
+    Public,
+    // 一覧には表示されないが、URLを知っていれば誰でも参加できる
+    #[default]
+    Unlisted,
+    // 一覧に表示されず、ロックされた状態で作られる。ホストがロックを解除するまで他のプレイヤーは参加できない
+    Private,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GamePhase {
+    // 参加者が準備完了するのを待っている。駒は動かせない
+    Lobby,
+    Playing,
+    // 勝敗が決まった。双方が再戦を希望すると盤面が初期化される
+    Finished,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct GameSessionConfig {
     pub board_size: usize,
     pub board_style: GameSessionBoardStyle,
     pub team_player_limit: usize,
+    pub team_mode: TeamMode,
+    pub swap_sides_on_rematch: bool,
+    // この項目がない古いファイルから復元した部屋は、既定値になる
+    #[serde(default)]
+    pub visibility: Visibility,
+    // レーティング戦の場合、勝敗が決まった時に参加者のレーティングが更新される
+    #[serde(default)]
+    pub rated: bool,
 }
 
 impl Default for GameSessionConfig {
     fn default() -> Self {
+        let room_config = &config::get().room;
         Self {
-            board_size: DEFAULT_BOARD_SIZE,
-            board_style: Default::default(),
-            team_player_limit: DEFAULT_TEAM_PLAYER_LIMIT,
+            board_size: room_config.board_size,
+            board_style: room_config.board_style,
+            team_player_limit: room_config.team_player_limit,
+            team_mode: room_config.team_mode,
+            swap_sides_on_rematch: false,
+            visibility: room_config.visibility,
+            rated: false,
         }
     }
 }
 
+/// A WebSocket connection authenticated as a player.
+#[derive(Debug)]
+struct PlayerConnection {
+    connection_id: Uuid,
+    // 送ると、その接続は閉じられる
+    kick_tx: oneshot::Sender<()>,
+}
+
 #[derive(Debug)]
 pub struct GameSession {
+    room_id: Uuid,
     config: GameSessionConfig,
     room_queue: broadcast::Sender<RoomEventWithId>,
     players: HashMap<Uuid, PlayerData>,
+    // プレイヤーのprivate_idごとの認証済みの接続。古い順
+    connections: HashMap<Uuid, Vec<PlayerConnection>>,
     pieces: Vec<Vec<Option<PieceData>>>,
+    phase: GamePhase,
+    // 部屋を作成したプレイヤー(または権限を譲られたプレイヤー)のtoken
+    host: Option<Uuid>,
+    is_locked: bool,
+    winner: Option<Side>,
+    rematch_requests: HashSet<Side>,
+    // 再戦のたびに先手を入れ替えるため
+    first_turn: Side,
     current_turn: Side,
-    // このstructがdropした際にHeartbeatTimerをdropするため
-    #[allow(dead_code)]
-    heartbeat_timer: HeartbeatTimer,
+    next_join_order: u64,
+    // Rotationモードで、それぞれの側で最後に手番を終えたプレイヤーのjoin_order
+    last_movers: HashMap<Side, u64>,
+    announced_turn_player: Option<Uuid>,
+    // レーティング戦で、現在の対局を始めた時に席についていたプレイヤー
+    rated_lineup: Vec<RatedPlayer>,
+    // 現在の(または最後の)対局の記録のID
+    game_id: Option<Uuid>,
+    // 再起動をまたいでも部屋の経過時間がわかるように、Instantではなく時刻で持つ
+    created_at: SystemTime,
 }
 
 pub struct ActionRejectedMarker;
 
+/// Why a player could not join a room.
+#[derive(Debug, Clone, Copy)]
+pub enum JoinRejection {
+    RoomLocked,
+    NameAlreadyUsed,
+    PlayerLimitExceeded,
+}
+
+impl JoinRejection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::RoomLocked => "ROOM_LOCKED",
+            Self::NameAlreadyUsed => "NAME_ALREADY_USED",
+            Self::PlayerLimitExceeded => "PLAYER_LIMIT_EXCEEDED",
+        }
+    }
+}
+
+/// Why a move was rejected. Clients just get `NotAccepted`.
+#[derive(Debug, Clone, Copy)]
+pub enum MoveRejection {
+    NotPlaying,
+    OutOfBoard,
+    NotYourTurn,
+    NotYourPiece,
+    IllegalMove,
+}
+
+impl MoveRejection {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::NotPlaying => "not_playing",
+            Self::OutOfBoard => "out_of_board",
+            Self::NotYourTurn => "not_your_turn",
+            Self::NotYourPiece => "not_your_piece",
+            Self::IllegalMove => "illegal_move",
+        }
+    }
+}
+
 impl GameSession {
-    pub fn new(room_id: Uuid, config: GameSessionConfig) -> Self {
+    pub fn new(room_id: Uuid, config: GameSessionConfig, host: Option<Uuid>) -> Self {
         if config.board_size < 7 {
             panic!("board size must be 7 or above");
         }
-        let mut pieces = vec![vec![None; config.board_size]; config.board_size];
-        match config.board_style {
-            GameSessionBoardStyle::Checker => {
-                for i in 0..3 {
-                    for j in 0..(config.board_size / 2) {
-                        let top_square = j * 2 + ((config.board_size - i) % 2);
-                        let bottom_square = j * 2 + ((i + 1) % 2);
-                        if top_square < config.board_size {
-                            pieces[i][top_square] = Some(PieceData {
-                                side: Side::Top,
-                                number: INITIAL_NUMBER,
-                            });
-                        }
-                        if bottom_square < config.board_size {
-                            pieces[config.board_size - (i + 1)][bottom_square] = Some(PieceData {
-                                side: Side::Bottom,
-                                number: INITIAL_NUMBER,
-                            });
-                        }
-                    }
-                }
-            }
-            GameSessionBoardStyle::Chess => {
-                for i in 0..2 {
-                    for j in 0..config.board_size {
-                        pieces[i][j] = Some(PieceData {
-                            side: Side::Top,
-                            number: INITIAL_NUMBER,
-                        });
-                        pieces[config.board_size - (i + 1)][j] = Some(PieceData {
-                            side: Side::Bottom,
-                            number: INITIAL_NUMBER,
-                        });
-                    }
-                }
-            }
-        }
+        let pieces = board::create_initial_pieces(config.board_size, config.board_style);
         Self {
+            room_id,
             config,
-            room_queue: broadcast::channel(QUEUE_MESSAGE_LIMIT).0,
+            room_queue: broadcast::channel(config::get().room.queue_message_limit).0,
             players: HashMap::new(),
+            connections: HashMap::new(),
             pieces,
+            phase: GamePhase::Lobby,
+            host,
+            is_locked: config.visibility == Visibility::Private,
+            winner: None,
+            rematch_requests: HashSet::new(),
+            first_turn: Side::Bottom,
             current_turn: Side::Bottom,
-            heartbeat_timer: HeartbeatTimer::new(room_id),
+            next_join_order: 0,
+            last_movers: HashMap::new(),
+            announced_turn_player: None,
+            rated_lineup: Vec::new(),
+            game_id: None,
+            created_at: SystemTime::now(),
         }
     }
 
@@ -197,6 +278,26 @@ impl GameSession {
         self.config.board_size
     }
 
+    pub fn get_host_name(&self) -> Option<&str> {
+        let host = self.host?;
+        self.players
+            .values()
+            .find(|data| data.token == host)
+            .map(|data| data.name.as_str())
+    }
+
+    pub fn get_team_mode(&self) -> TeamMode {
+        self.config.team_mode
+    }
+
+    pub fn get_config(&self) -> GameSessionConfig {
+        self.config
+    }
+
+    pub fn get_age(&self) -> Duration {
+        self.created_at.elapsed().unwrap_or_default()
+    }
+
     pub fn get_queue_sender(&self) -> broadcast::Sender<RoomEventWithId> {
         self.room_queue.clone()
     }
@@ -217,20 +318,16 @@ impl GameSession {
         &self.pieces
     }
 
-    fn get_pieces_mut(&mut self) -> &mut Vec<Vec<Option<PieceData>>> {
-        &mut self.pieces
-    }
-
-    pub fn create_player<T>(&mut self, side: Side, name: T) -> Option<Uuid>
-    where
-        T: Into<String>,
-    {
+    pub fn create_player(&mut self, side: Side, token: Uuid, identity: &Identity) -> Option<Uuid> {
         if self.get_player_data(side).len() >= self.config.team_player_limit {
             return None;
         }
         let private_id = Uuid::new_v4();
-        let public_id = Uuid::new_v4();
-        let name = name.into();
+        let public_id = identity.public_id;
+        let name = identity.name.clone();
+        let join_order = self.next_join_order;
+        self.next_join_order += 1;
+        let last_heartbeat = Instant::now();
         self.players.insert(
             private_id,
             PlayerData {
@@ -238,10 +335,16 @@ impl GameSession {
                 name: name.to_owned(),
                 selecting_piece: None,
                 is_inactive: false,
-                last_heartbeat: Instant::now(),
+                is_ready: false,
+                is_disconnected: false,
+                last_heartbeat,
+                disconnected_at: None,
                 side,
+                token,
+                join_order,
             },
         );
+        self.schedule_heartbeat_check(private_id, last_heartbeat);
         let _ = self.room_queue.send(RoomEventWithId {
             public_id,
             event: match side {
@@ -249,10 +352,13 @@ impl GameSession {
                 Side::Bottom => RoomEvent::BottomPlayerJoin(name),
             },
         });
+        self.announce_turn_player();
         Some(private_id)
     }
 
     pub fn remove_player(&mut self, private_id: Uuid) -> bool {
+        // kick_txがdropするので、接続中のWebSocketには席を失ったことが伝わる
+        self.connections.remove(&private_id);
         match self.players.remove(&private_id) {
             Some(previous_data) => {
                 let _ = self.room_queue.send(RoomEventWithId {
@@ -262,14 +368,99 @@ impl GameSession {
                         Side::Bottom => RoomEvent::BottomPlayerLeave,
                     },
                 });
+                // レーティング戦で片方の側が誰もいなくなったら、その側の負けとする
+                if self.config.rated
+                    && self.phase == GamePhase::Playing
+                    && self.get_player_data(previous_data.side).is_empty()
+                {
+                    self.forfeit_game(previous_data.side, previous_data.public_id);
+                }
+                self.announce_turn_player();
                 true
             }
             None => false,
         }
     }
 
-    pub fn contains_player(&self, private_id: Uuid) -> bool {
-        self.players.contains_key(&private_id)
+    pub fn is_name_taken(&self, name: &str) -> bool {
+        self.players.values().any(|data| data.name == name)
+    }
+
+    pub fn rename_player<T>(
+        &mut self,
+        private_id: Uuid,
+        name: T,
+    ) -> Result<(), ActionRejectedMarker>
+    where
+        T: Into<String>,
+    {
+        let name = name.into();
+        let player = self.players.get(&private_id).ok_or(ActionRejectedMarker)?;
+        if player.name == name {
+            return Ok(());
+        }
+        if self.is_name_taken(&name) {
+            return Err(ActionRejectedMarker);
+        }
+        let player = self.get_player_mut(private_id).unwrap();
+        player.name = name.clone();
+        let public_id = player.public_id;
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::PlayerRename(name),
+        });
+        Ok(())
+    }
+
+    /// Seats the player holding `token` on `side`, or returns their seat if they already have one.
+    /// If the name is already used in this room, the seat gets another name, but `identity` is
+    /// left as it is.
+    pub fn join_player(
+        &mut self,
+        side: Side,
+        token: Uuid,
+        identity: &Identity,
+        requested_name: Option<String>,
+    ) -> Result<Uuid, JoinRejection> {
+        // 同じtokenのプレイヤーが既に席についている場合は、その席を返す
+        if let Some(private_id) = self.find_player_by_token(token) {
+            return Ok(private_id);
+        }
+        // ホストは部屋がロックされていても参加できる
+        if self.is_locked && self.get_host() != Some(token) {
+            return Err(JoinRejection::RoomLocked);
+        }
+        let name = match requested_name {
+            Some(name) if self.is_name_taken(&name) => {
+                return Err(JoinRejection::NameAlreadyUsed);
+            }
+            Some(name) => name,
+            // 他の部屋やランキングでの名前は変えず、この部屋の席でだけ別の名前を使う
+            None => {
+                let mut name = identity.name.clone();
+                while self.is_name_taken(&name) {
+                    name = generate_name();
+                }
+                name
+            }
+        };
+        let identity = Identity {
+            public_id: identity.public_id,
+            name,
+        };
+        self.create_player(side, token, &identity)
+            .ok_or(JoinRejection::PlayerLimitExceeded)
+    }
+
+    pub fn find_player_by_token(&self, token: Uuid) -> Option<Uuid> {
+        self.players
+            .iter()
+            .find(|(_, data)| data.token == token)
+            .map(|(private_id, _)| *private_id)
+    }
+
+    pub fn get_player(&self, private_id: Uuid) -> Option<&PlayerData> {
+        self.players.get(&private_id)
     }
 
     fn get_player_mut(&mut self, private_id: Uuid) -> Option<&mut PlayerData> {
@@ -282,21 +473,539 @@ impl GameSession {
         self.players.get(&private_id).unwrap().public_id
     }
 
+    fn schedule_heartbeat_check(&self, private_id: Uuid, last_heartbeat: Instant) {
+        let inactive_threshold = Duration::from_secs(config::get().player.inactive_threshold);
+        heartbeat::schedule(
+            self.room_id,
+            private_id,
+            PlayerCheck::Heartbeat,
+            last_heartbeat + inactive_threshold,
+        );
+    }
+
+    fn schedule_reconnect_check(&self, private_id: Uuid, disconnected_at: Instant) {
+        let reconnect_grace = Duration::from_secs(config::get().player.reconnect_grace);
+        heartbeat::schedule(
+            self.room_id,
+            private_id,
+            PlayerCheck::Reconnect,
+            disconnected_at + reconnect_grace,
+        );
+    }
+
+    /// Binds a WebSocket connection to the player, and returns its ID. `kick_tx` is sent to
+    /// when the connection has to be closed, or dropped when the player loses the seat.
+    /// With `player.kick_duplicate_connections`, the player's older connections are kicked.
+    /// Returns `None` if the player is not in the room.
+    pub fn connect_player(
+        &mut self,
+        private_id: Uuid,
+        kick_tx: oneshot::Sender<()>,
+    ) -> Option<Uuid> {
+        if !self.update_heartbeat(private_id) {
+            return None;
+        }
+        let connection_id = Uuid::new_v4();
+        let connections = self.connections.entry(private_id).or_default();
+        if config::get().player.kick_duplicate_connections {
+            for connection in connections.drain(..) {
+                let _ = connection.kick_tx.send(());
+            }
+        }
+        connections.push(PlayerConnection {
+            connection_id,
+            kick_tx,
+        });
+        let player = self.get_player_mut(private_id).unwrap();
+        player.disconnected_at = None;
+        if player.is_disconnected {
+            player.is_disconnected = false;
+            let public_id = player.public_id;
+            let _ = self.room_queue.send(RoomEventWithId {
+                public_id,
+                event: RoomEvent::PlayerReconnect,
+            });
+        }
+        Some(connection_id)
+    }
+
+    /// Unbinds a closed connection. When it was the player's last one, they are marked
+    /// disconnected, and the seat is freed unless they connect again within
+    /// `player.reconnect_grace`.
+    pub fn disconnect_player(&mut self, private_id: Uuid, connection_id: Uuid) {
+        // 追い出された接続や、席を失った後の接続は、既に外されている
+        let Some(connections) = self.connections.get_mut(&private_id) else {
+            return;
+        };
+        let Some(index) = connections
+            .iter()
+            .position(|connection| connection.connection_id == connection_id)
+        else {
+            return;
+        };
+        connections.remove(index);
+        if !connections.is_empty() {
+            return;
+        }
+        self.connections.remove(&private_id);
+        let Some(player) = self.get_player_mut(private_id) else {
+            return;
+        };
+        let disconnected_at = Instant::now();
+        player.disconnected_at = Some(disconnected_at);
+        player.is_disconnected = true;
+        let public_id = player.public_id;
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::PlayerDisconnect,
+        });
+        self.schedule_reconnect_check(private_id, disconnected_at);
+    }
+
+    /// Called by the heartbeat sweeper when the player's reconnect grace period has passed.
+    /// Removes the player if they have not connected since. Returns `true` if they were removed.
+    pub fn check_reconnect(&mut self, private_id: Uuid) -> bool {
+        let reconnect_grace = Duration::from_secs(config::get().player.reconnect_grace);
+        // 途中で再接続した場合や、一度再接続してからまた切断した場合は何もしない
+        let is_expired = self.get_player(private_id).is_some_and(|player| {
+            player
+                .disconnected_at
+                .is_some_and(|disconnected_at| disconnected_at.elapsed() >= reconnect_grace)
+        });
+        is_expired && self.remove_player(private_id)
+    }
+
+    /// Returns `false` if the player is not in the room.
+    pub fn update_heartbeat(&mut self, private_id: Uuid) -> bool {
+        let Some(player) = self.get_player_mut(private_id) else {
+            return false;
+        };
+        player.last_heartbeat = Instant::now();
+        if player.is_inactive {
+            player.is_inactive = false;
+            let public_id = player.public_id;
+            let _ = self.room_queue.send(RoomEventWithId {
+                public_id,
+                event: RoomEvent::PlayerActive,
+            });
+        }
+        true
+    }
+
+    /// Called by the heartbeat sweeper when the player's deadline has passed.
+    /// Marks the player inactive or removes them, and schedules the next check.
+    /// Returns `true` if the player was changed.
+    pub fn check_heartbeat(&mut self, private_id: Uuid) -> bool {
+        let room_id = self.room_id;
+        let player_config = &config::get().player;
+        let inactive_threshold = Duration::from_secs(player_config.inactive_threshold);
+        let kick_threshold = Duration::from_secs(player_config.kick_threshold);
+        // 既に部屋を出たプレイヤーの確認は、何もせずに終わる
+        let Some(player) = self.get_player_mut(private_id) else {
+            return false;
+        };
+        let elapsed = player.last_heartbeat.elapsed();
+        if elapsed >= kick_threshold {
+            self.remove_player(private_id);
+            return true;
+        }
+        // 前回の確認からheartbeatが届いていれば、次の期限まで何もしない
+        // 活動の再開はupdate_heartbeatで扱うので、ここでは非アクティブになる時だけを扱う
+        if elapsed < inactive_threshold {
+            heartbeat::schedule(
+                room_id,
+                private_id,
+                PlayerCheck::Heartbeat,
+                player.last_heartbeat + inactive_threshold,
+            );
+            return false;
+        }
+        heartbeat::schedule(
+            room_id,
+            private_id,
+            PlayerCheck::Heartbeat,
+            player.last_heartbeat + kick_threshold,
+        );
+        if player.is_inactive {
+            return false;
+        }
+        player.is_inactive = true;
+        let public_id = player.public_id;
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::PlayerInactive,
+        });
+        true
+    }
+
+    pub fn get_phase(&self) -> GamePhase {
+        self.phase
+    }
+
     /// # This function will panic if ID is invalid.
     /// Double-check the argument.
-    pub fn update_heartbeat(&mut self, private_id: Uuid) {
-        self.get_player_mut(private_id).unwrap().last_heartbeat = Instant::now();
+    pub fn set_ready(
+        &mut self,
+        private_id: Uuid,
+        is_ready: bool,
+    ) -> Result<(), ActionRejectedMarker> {
+        if self.phase != GamePhase::Lobby {
+            return Err(ActionRejectedMarker);
+        }
+        let player = self.get_player_mut(private_id).unwrap();
+        if player.is_ready == is_ready {
+            return Ok(());
+        }
+        player.is_ready = is_ready;
+        let public_id = player.public_id;
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::PlayerReady(is_ready),
+        });
+        let is_side_ready = |side| {
+            self.players
+                .values()
+                .any(|data| data.side == side && data.is_ready)
+        };
+        if is_side_ready(Side::Top) && is_side_ready(Side::Bottom) {
+            let _ = self.start_game(public_id);
+        }
+        Ok(())
+    }
+
+    /// Moves the session from the lobby into play.
+    /// `public_id` is reported as the player who triggered the start.
+    pub fn start_game(&mut self, public_id: Uuid) -> Result<(), ActionRejectedMarker> {
+        if self.phase != GamePhase::Lobby {
+            return Err(ActionRejectedMarker);
+        }
+        self.phase = GamePhase::Playing;
+        self.record_game_start();
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::GameStart,
+        });
+        Ok(())
+    }
+
+    /// Starts the record of the game, and remembers who is rated for it.
+    fn record_game_start(&mut self) {
+        let mut players = self.players.values().collect::<Vec<_>>();
+        players.sort_unstable_by_key(|data| data.join_order);
+        self.game_id = Some(archive::begin_game(
+            self.room_id,
+            self.config.board_size,
+            self.config.board_style,
+            players
+                .iter()
+                .map(|data| RecordedPlayer {
+                    public_id: data.public_id,
+                    name: data.name.clone(),
+                    side: data.side,
+                })
+                .collect(),
+            self.current_turn,
+        ));
+        if !self.config.rated {
+            return;
+        }
+        self.rated_lineup = players
+            .iter()
+            .map(|data| RatedPlayer {
+                public_id: data.public_id,
+                name: data.name.clone(),
+                side: data.side,
+            })
+            .collect();
+    }
+
+    pub fn get_game_id(&self) -> Option<Uuid> {
+        self.game_id
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.is_locked
+    }
+
+    pub fn get_host(&self) -> Option<Uuid> {
+        self.host
+    }
+
+    pub fn is_host(&self, private_id: Uuid) -> bool {
+        self.players
+            .get(&private_id)
+            .is_some_and(|data| self.host == Some(data.token))
+    }
+
+    pub fn get_host_public_id(&self) -> Option<Uuid> {
+        self.players
+            .values()
+            .find(|data| self.host == Some(data.token))
+            .map(|data| data.public_id)
+    }
+
+    pub fn find_player_by_public_id(&self, public_id: Uuid) -> Option<Uuid> {
+        self.players
+            .iter()
+            .find(|(_, data)| data.public_id == public_id)
+            .map(|(private_id, _)| *private_id)
+    }
+
+    // これより下のhost_で始まる関数は、呼び出し側でis_hostを確認してから呼ぶこと
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    /// Rated games cannot be changed this way while they are being played, since emptying a side
+    /// ends the game as its loss.
+    pub fn host_kick_player(
+        &mut self,
+        host_private_id: Uuid,
+        target_public_id: Uuid,
+    ) -> Result<(), ActionRejectedMarker> {
+        if self.config.rated && self.phase == GamePhase::Playing {
+            return Err(ActionRejectedMarker);
+        }
+        let target_private_id = self
+            .find_player_by_public_id(target_public_id)
+            .ok_or(ActionRejectedMarker)?;
+        if target_private_id == host_private_id {
+            return Err(ActionRejectedMarker);
+        }
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: self.get_public_id(host_private_id),
+            event: RoomEvent::PlayerKick(target_public_id),
+        });
+        self.remove_player(target_private_id);
+        Ok(())
+    }
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    pub fn host_set_locked(&mut self, host_private_id: Uuid, is_locked: bool) {
+        if self.is_locked == is_locked {
+            return;
+        }
+        self.is_locked = is_locked;
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: self.get_public_id(host_private_id),
+            event: RoomEvent::RoomLock(is_locked),
+        });
+    }
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    /// Rated games cannot be changed this way while they are being played, so that the rated
+    /// lineup stays the one the game started with.
+    pub fn host_change_side(
+        &mut self,
+        host_private_id: Uuid,
+        target_public_id: Uuid,
+        side: Side,
+    ) -> Result<(), ActionRejectedMarker> {
+        if self.config.rated && self.phase == GamePhase::Playing {
+            return Err(ActionRejectedMarker);
+        }
+        let target_private_id = self
+            .find_player_by_public_id(target_public_id)
+            .ok_or(ActionRejectedMarker)?;
+        if self.players.get(&target_private_id).unwrap().side == side {
+            return Ok(());
+        }
+        if self.get_player_data(side).len() >= self.config.team_player_limit {
+            return Err(ActionRejectedMarker);
+        }
+        let join_order = self.next_join_order;
+        self.next_join_order += 1;
+        let player = self.get_player_mut(target_private_id).unwrap();
+        player.side = side;
+        player.selecting_piece = None;
+        player.is_ready = false;
+        // 移った先の側では、最後に参加したプレイヤーとして扱う
+        player.join_order = join_order;
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: self.get_public_id(host_private_id),
+            event: RoomEvent::SideChange(target_public_id, side),
+        });
+        self.announce_turn_player();
+        Ok(())
+    }
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    /// Rated games cannot be reset while they are being played, so that the losing side
+    /// cannot escape the rating change.
+    pub fn host_reset_board(&mut self, host_private_id: Uuid) -> Result<(), ActionRejectedMarker> {
+        if self.config.rated && self.phase == GamePhase::Playing {
+            return Err(ActionRejectedMarker);
+        }
+        self.abort_game();
+        self.reset_board();
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: self.get_public_id(host_private_id),
+            event: RoomEvent::BoardReset,
+        });
+        self.announce_turn_player();
+        Ok(())
+    }
+
+    /// Marks the record of the game being played as aborted. Does nothing outside `Playing`.
+    pub fn abort_game(&mut self) {
+        if self.phase != GamePhase::Playing {
+            return;
+        }
+        if let Some(game_id) = self.game_id {
+            archive::abort_game(game_id);
+        }
+    }
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    pub fn host_transfer(
+        &mut self,
+        host_private_id: Uuid,
+        target_public_id: Uuid,
+    ) -> Result<(), ActionRejectedMarker> {
+        let target_private_id = self
+            .find_player_by_public_id(target_public_id)
+            .ok_or(ActionRejectedMarker)?;
+        self.host = Some(self.players.get(&target_private_id).unwrap().token);
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: self.get_public_id(host_private_id),
+            event: RoomEvent::HostTransfer(target_public_id),
+        });
+        Ok(())
+    }
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    /// Kicks a player on behalf of the server operator rather than the host.
+    pub fn admin_kick_player(
+        &mut self,
+        target_public_id: Uuid,
+    ) -> Result<(), ActionRejectedMarker> {
+        let target_private_id = self
+            .find_player_by_public_id(target_public_id)
+            .ok_or(ActionRejectedMarker)?;
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: Uuid::nil(),
+            event: RoomEvent::PlayerKick(target_public_id),
+        });
+        self.remove_player(target_private_id);
+        Ok(())
+    }
+
+    pub fn send_notice(&self, message: &str) {
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: Uuid::nil(),
+            event: RoomEvent::ServerNotice(message.to_owned()),
+        });
+    }
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    pub fn request_rematch(&mut self, private_id: Uuid) -> Result<(), ActionRejectedMarker> {
+        if self.phase != GamePhase::Finished {
+            return Err(ActionRejectedMarker);
+        }
+        let player = self.players.get(&private_id).unwrap();
+        let (side, public_id) = (player.side, player.public_id);
+        if !self.rematch_requests.insert(side) {
+            return Ok(());
+        }
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::RematchRequest,
+        });
+        if self.rematch_requests.len() < 2 {
+            return Ok(());
+        }
+        if self.config.swap_sides_on_rematch {
+            for player in self.players.values_mut() {
+                player.side = player.side.opposite();
+            }
+        }
+        self.first_turn = self.first_turn.opposite();
+        self.reset_board();
+        // 双方が再戦に同意しているので、ロビーを経由せずに始める
+        self.phase = GamePhase::Playing;
+        self.record_game_start();
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::Rematch,
+        });
+        self.announce_turn_player();
+        Ok(())
+    }
+
+    pub fn get_winner(&self) -> Option<Side> {
+        self.winner
+    }
+
+    /// 盤面を初期配置に戻し、ロビーからやり直します。
+    fn reset_board(&mut self) {
+        self.pieces = board::create_initial_pieces(self.config.board_size, self.config.board_style);
+        self.phase = GamePhase::Lobby;
+        self.winner = None;
+        self.rematch_requests.clear();
+        self.current_turn = self.first_turn;
+        self.last_movers.clear();
+        for player in self.players.values_mut() {
+            player.selecting_piece = None;
+            player.is_ready = false;
+        }
     }
 
     pub fn get_current_turn(&self) -> Side {
         self.current_turn
     }
 
-    fn toggle_turn(&mut self) {
-        self.current_turn = match self.current_turn {
-            Side::Top => Side::Bottom,
-            Side::Bottom => Side::Top,
-        };
+    /// Returns the private ID of the only player allowed to move in this turn,
+    /// or `None` if anyone on the current side may move.
+    pub fn get_turn_player(&self) -> Option<Uuid> {
+        let mut candidates = self
+            .players
+            .iter()
+            .filter(|(_, data)| data.side == self.current_turn)
+            .map(|(private_id, data)| (data.join_order, *private_id))
+            .collect::<Vec<_>>();
+        candidates.sort_unstable();
+        match self.config.team_mode {
+            TeamMode::FreeForAll => None,
+            TeamMode::Captain => candidates.first().map(|(_, private_id)| *private_id),
+            TeamMode::Rotation => {
+                let last_mover = self.last_movers.get(&self.current_turn);
+                candidates
+                    .iter()
+                    .find(|(join_order, _)| last_mover.is_none_or(|last| join_order > last))
+                    .or(candidates.first())
+                    .map(|(_, private_id)| *private_id)
+            }
+        }
+    }
+
+    fn announce_turn_player(&mut self) {
+        let turn_player = self
+            .get_turn_player()
+            .map(|private_id| self.get_public_id(private_id));
+        if turn_player == self.announced_turn_player {
+            return;
+        }
+        self.announced_turn_player = turn_player;
+        if let Some(public_id) = turn_player {
+            let _ = self.room_queue.send(RoomEventWithId {
+                public_id,
+                event: RoomEvent::TurnPlayer,
+            });
+        }
+    }
+
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    fn finish_turn(&mut self, private_id: Uuid) {
+        let join_order = self.players.get(&private_id).unwrap().join_order;
+        self.last_movers.insert(self.current_turn, join_order);
+        self.current_turn = self.current_turn.opposite();
+        self.announce_turn_player();
     }
 
     /// # This function will panic if ID is invalid.
@@ -306,76 +1015,49 @@ impl GameSession {
         private_id: Uuid,
         position: Position,
     ) -> Result<(), ActionRejectedMarker> {
-        let board_size = self.get_board_size();
-        let (x, y) = position;
-        if x > board_size || y > board_size {
+        if !board::is_on_board(&self.pieces, position) {
             return Err(ActionRejectedMarker);
         }
         self.get_player_mut(private_id).unwrap().selecting_piece = Some(position);
-        self.get_queue_sender()
-            .send(RoomEventWithId {
-                public_id: self.get_public_id(private_id),
-                event: RoomEvent::SelectPiece(position),
-            })
-            .unwrap();
+        // 誰も接続していなければ送信は失敗するが、他の出来事と同じく気にしない
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id: self.get_public_id(private_id),
+            event: RoomEvent::SelectPiece(position),
+        });
         Ok(())
     }
 
-    fn is_any_piece_still_movable(&self, side: Side) -> bool {
-        let board_size = self.get_board_size();
-        for (y, row) in self.pieces.iter().enumerate() {
-            for (x, piece) in row.iter().enumerate() {
-                if !piece.is_some_and(|p| p.side == side) {
-                    continue;
-                }
-                let number = piece.unwrap().number;
-                // x
-                //   o
-                //
-                if x >= 2
-                    && y >= 2
-                    && self.pieces[y - 1][x - 1]
-                        .is_some_and(|p| side != p.side && number > p.number)
-                    && self.pieces[y - 2][x - 2].is_none()
-                {
-                    return true;
-                }
-                //     x
-                //   o
-                //
-                if x <= board_size - 3
-                    && y >= 2
-                    && self.pieces[y - 1][x + 1]
-                        .is_some_and(|p| side != p.side && number > p.number)
-                    && self.pieces[y - 2][x + 2].is_none()
-                {
-                    return true;
-                }
-                //
-                //   o
-                // x
-                if x >= 2
-                    && y <= board_size - 3
-                    && self.pieces[y + 1][x - 1]
-                        .is_some_and(|p| side != p.side && number > p.number)
-                    && self.pieces[y + 2][x - 2].is_none()
-                {
-                    return true;
-                }
-                //
-                //   o
-                //     x
-                if x <= board_size - 3
-                    && y <= board_size - 3
-                    && self.pieces[y + 1][x + 1]
-                        .is_some_and(|p| side != p.side && number > p.number)
-                    && self.pieces[y + 2][x + 2].is_none()
-                {
-                    return true;
-                }
-            }
+    /// # This function will panic if ID is invalid.
+    /// Double-check the argument.
+    fn check_game_end(&mut self, private_id: Uuid) {
+        let Some(winner) = board::judge_winner(&self.pieces, self.current_turn) else {
+            return;
+        };
+        if let Some(game_id) = self.game_id {
+            archive::finish_game(game_id, winner);
         }
-        false
+        self.end_game(winner, self.get_public_id(private_id));
+    }
+
+    /// Ends the game being played as a loss for `side`, which has no players left.
+    /// `public_id` is reported as the player whose leaving ended the game.
+    fn forfeit_game(&mut self, side: Side, public_id: Uuid) {
+        // 盤面の上では決着していないので、記録は打ち切られた対局として残す
+        self.abort_game();
+        self.end_game(side.opposite(), public_id);
+    }
+
+    fn end_game(&mut self, winner: Side, public_id: Uuid) {
+        self.phase = GamePhase::Finished;
+        self.winner = Some(winner);
+        metrics::GAMES_FINISHED.inc(&[winner.as_str()]);
+        if self.config.rated {
+            rating::record_game(self.room_id, winner, &self.rated_lineup);
+        }
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::GameEnd(winner),
+        });
     }
 
     /// # This function will panic if ID is invalid.
@@ -386,104 +1068,170 @@ impl GameSession {
         old_position: Position,
         new_position: Position,
     ) -> Result<(), ActionRejectedMarker> {
-        let board_size = self.get_board_size();
-        let ((old_x, old_y), (new_x, new_y)) = (old_position, new_position);
-        if new_x > board_size || new_y > board_size {
-            return Err(ActionRejectedMarker);
+        match self.try_move_piece(private_id, old_position, new_position) {
+            Ok(()) => {
+                metrics::MOVES_ACCEPTED.inc();
+                self.check_game_end(private_id);
+                Ok(())
+            }
+            Err(rejection) => {
+                metrics::MOVES_REJECTED.inc(&[rejection.as_str()]);
+                Err(ActionRejectedMarker)
+            }
+        }
+    }
+
+    fn try_move_piece(
+        &mut self,
+        private_id: Uuid,
+        old_position: Position,
+        new_position: Position,
+    ) -> Result<(), MoveRejection> {
+        if self.phase != GamePhase::Playing {
+            return Err(MoveRejection::NotPlaying);
         }
         let player_side = self.players.get(&private_id).unwrap().side;
         if player_side != self.get_current_turn() {
-            return Err(ActionRejectedMarker);
+            return Err(MoveRejection::NotYourTurn);
         }
-        let moving_piece_number = match self.pieces[old_y][old_x] {
-            Some(piece) if piece.side == player_side => piece.number,
-            _ => return Err(ActionRejectedMarker),
-        };
-        let destination_piece = self.pieces[new_y][new_x];
-        let x_diff = old_x.abs_diff(new_x);
-        let y_diff = old_y.abs_diff(new_y);
-        // 敵の駒を取る
-        if x_diff == 2 && y_diff == 2 {
-            if destination_piece.is_some() {
-                return Err(ActionRejectedMarker);
-            }
-            let between_x = {
-                if old_x > new_x {
-                    old_x - 1
-                } else {
-                    old_x + 1
-                }
-            };
-            let between_y = {
-                if old_y > new_y {
-                    old_y - 1
-                } else {
-                    old_y + 1
-                }
-            };
-            let between_piece = self.pieces[between_y][between_x];
-            if !between_piece.is_some_and(|p| moving_piece_number > p.number) {
-                return Err(ActionRejectedMarker);
-            }
-            {
-                let pieces_mut = self.get_pieces_mut();
-                pieces_mut[new_y][new_x] = Some(PieceData {
+        if self
+            .get_turn_player()
+            .is_some_and(|turn_player| turn_player != private_id)
+        {
+            return Err(MoveRejection::NotYourTurn);
+        }
+        let outcome = board::apply_move(&mut self.pieces, player_side, old_position, new_position)?;
+        let public_id = self.get_public_id(private_id);
+        if let Some(game_id) = self.game_id {
+            archive::record_move(
+                game_id,
+                RecordedMove {
                     side: player_side,
-                    number: ((moving_piece_number as f32) * (2.0 / 3.0)) as u8,
-                });
-                pieces_mut[between_y][between_x] = None;
-                pieces_mut[old_y][old_x] = None;
-            }
-            self.get_queue_sender()
-                .send(RoomEventWithId {
-                    public_id: self.get_public_id(private_id),
-                    event: RoomEvent::MovePiece(old_position, new_position),
-                })
-                .unwrap();
-            if !self.is_any_piece_still_movable(player_side) {
-                self.toggle_turn();
-            }
-            Ok(())
-        } else if x_diff == 1 && y_diff == 1 {
-            if self.is_any_piece_still_movable(player_side) {
-                return Err(ActionRejectedMarker);
-            }
-            match destination_piece {
-                Some(piece) if piece.side == player_side && moving_piece_number > 2 => {
-                    {
-                        let pieces_mut = self.get_pieces_mut();
-                        pieces_mut[new_y][new_x].as_mut().unwrap().number +=
-                            moving_piece_number.div_ceil(2);
-                        pieces_mut[old_y][old_x].as_mut().unwrap().number = moving_piece_number / 2;
-                    }
-                    self.get_queue_sender()
-                        .send(RoomEventWithId {
-                            public_id: self.get_public_id(private_id),
-                            event: RoomEvent::MovePiece(old_position, new_position),
-                        })
-                        .unwrap();
-                    self.toggle_turn();
-                    Ok(())
-                }
-                None => {
-                    {
-                        let pieces_mut = self.get_pieces_mut();
-                        pieces_mut[new_y][new_x] = pieces_mut[old_y][old_x];
-                        pieces_mut[old_y][old_x] = None;
-                    }
-                    self.get_queue_sender()
-                        .send(RoomEventWithId {
-                            public_id: self.get_public_id(private_id),
-                            event: RoomEvent::MovePiece(old_position, new_position),
-                        })
-                        .unwrap();
-                    self.toggle_turn();
-                    Ok(())
-                }
-                _ => Err(ActionRejectedMarker),
-            }
-        } else {
-            Err(ActionRejectedMarker)
+                    public_id,
+                    from: old_position,
+                    to: new_position,
+                },
+            );
+        }
+        let _ = self.room_queue.send(RoomEventWithId {
+            public_id,
+            event: RoomEvent::MovePiece(old_position, new_position),
+        });
+        if outcome == board::MoveOutcome::TurnFinished {
+            self.finish_turn(private_id);
         }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_session(team_mode: TeamMode) -> GameSession {
+        let config = GameSessionConfig {
+            team_mode,
+            team_player_limit: 3,
+            ..Default::default()
+        };
+        GameSession::new(Uuid::new_v4(), config, None)
+    }
+
+    fn create_rated_session() -> GameSession {
+        let config = GameSessionConfig {
+            rated: true,
+            ..Default::default()
+        };
+        GameSession::new(Uuid::new_v4(), config, None)
+    }
+
+    fn add_player(session: &mut GameSession, side: Side) -> Uuid {
+        let identity = Identity {
+            public_id: Uuid::new_v4(),
+            name: generate_name(),
+        };
+        session
+            .create_player(side, Uuid::new_v4(), &identity)
+            .unwrap()
+    }
+
+    // 心拍の確認を予約するためにランタイムが必要
+    #[tokio::test]
+    async fn rotation_passes_the_turn_in_join_order() {
+        let mut session = create_session(TeamMode::Rotation);
+        let bottom = [(); 3].map(|()| add_player(&mut session, Side::Bottom));
+        let top = add_player(&mut session, Side::Top);
+        for private_id in bottom {
+            assert_eq!(session.get_turn_player(), Some(private_id));
+            session.finish_turn(private_id);
+            // 一人しかいない側は、毎回同じプレイヤー
+            assert_eq!(session.get_turn_player(), Some(top));
+            session.finish_turn(top);
+        }
+        // 最後に参加したプレイヤーの次は、最初に戻る
+        assert_eq!(session.get_turn_player(), Some(bottom[0]));
+    }
+
+    #[tokio::test]
+    async fn rotation_skips_players_who_left() {
+        let mut session = create_session(TeamMode::Rotation);
+        let bottom = [(); 3].map(|()| add_player(&mut session, Side::Bottom));
+        let top = add_player(&mut session, Side::Top);
+        session.finish_turn(bottom[0]);
+        session.finish_turn(top);
+        session.remove_player(bottom[1]);
+        assert_eq!(session.get_turn_player(), Some(bottom[2]));
+        session.finish_turn(bottom[2]);
+        session.finish_turn(top);
+        assert_eq!(session.get_turn_player(), Some(bottom[0]));
+    }
+
+    #[tokio::test]
+    async fn other_team_modes_do_not_rotate() {
+        let mut session = create_session(TeamMode::Captain);
+        let bottom = [(); 2].map(|()| add_player(&mut session, Side::Bottom));
+        let top = add_player(&mut session, Side::Top);
+        session.finish_turn(bottom[0]);
+        session.finish_turn(top);
+        assert_eq!(session.get_turn_player(), Some(bottom[0]));
+
+        let mut session = create_session(TeamMode::FreeForAll);
+        add_player(&mut session, Side::Bottom);
+        assert_eq!(session.get_turn_player(), None);
+    }
+
+    #[tokio::test]
+    async fn leaving_a_rated_game_loses_it() {
+        let mut session = create_rated_session();
+        let top = add_player(&mut session, Side::Top);
+        let bottom = add_player(&mut session, Side::Bottom);
+        let bottom_public_id = session.get_public_id(bottom);
+        assert!(session.start_game(session.get_public_id(top)).is_ok());
+        session.remove_player(bottom);
+        assert_eq!(session.get_phase(), GamePhase::Finished);
+        assert_eq!(session.get_winner(), Some(Side::Top));
+        let profile = rating::get_profile_map().read()[&bottom_public_id].clone();
+        assert_eq!((profile.wins, profile.losses), (0, 1));
+        // 記録の上では決着していないので、打ち切られた対局になる
+        let game_id = session.get_game_id().unwrap();
+        assert!(archive::get_game_archive()
+            .read()
+            .get(game_id)
+            .unwrap()
+            .is_aborted());
+    }
+
+    #[tokio::test]
+    async fn the_host_cannot_change_the_lineup_of_a_rated_game() {
+        let mut session = create_rated_session();
+        let top = add_player(&mut session, Side::Top);
+        let bottom = add_player(&mut session, Side::Bottom);
+        let bottom_public_id = session.get_public_id(bottom);
+        assert!(session.start_game(session.get_public_id(top)).is_ok());
+        assert!(session
+            .host_change_side(top, bottom_public_id, Side::Top)
+            .is_err());
+        assert!(session.host_kick_player(top, bottom_public_id).is_err());
+        assert_eq!(session.get_phase(), GamePhase::Playing);
     }
 }
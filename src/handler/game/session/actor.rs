@@ -0,0 +1,440 @@
+use std::{future, time::Duration};
+
+use tokio::{
+    runtime,
+    sync::{mpsc, oneshot},
+    time::{self, Instant},
+};
+use uuid::Uuid;
+
+use crate::config;
+
+use super::{
+    super::{
+        identity::Identity,
+        lobby::{get_listing, notify_lobby},
+    },
+    heartbeat::PlayerCheck,
+    map::remove_room,
+    snapshot::GameSessionSnapshot,
+    GameSession, JoinRejection, Position, Side,
+};
+
+// 部屋ごとに溜めておける命令の数。溢れた場合は、送る側が空くまで待つ
+const COMMAND_BUFFER: usize = 64;
+
+type Inspection = Box<dyn FnOnce(&GameSession) + Send>;
+type Update = Box<dyn FnOnce(&mut GameSession) + Send>;
+
+/// The seat a player got by joining a room.
+#[derive(Debug, Clone)]
+pub struct JoinedPlayer {
+    pub private_id: Uuid,
+    pub public_id: Uuid,
+    pub side: Side,
+    pub name: String,
+}
+
+/// What became of an action sent by a player.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ActionOutcome {
+    Accepted,
+    Rejected,
+    /// The player has left or been removed from the room.
+    NotInRoom,
+}
+
+enum RoomCommand {
+    Join {
+        side: Side,
+        token: Uuid,
+        identity: Identity,
+        requested_name: Option<String>,
+        reply: oneshot::Sender<Result<JoinedPlayer, JoinRejection>>,
+    },
+    Leave {
+        private_id: Uuid,
+        reply: oneshot::Sender<bool>,
+    },
+    Select {
+        private_id: Uuid,
+        position: Position,
+        reply: oneshot::Sender<ActionOutcome>,
+    },
+    Move {
+        private_id: Uuid,
+        from: Position,
+        to: Position,
+        reply: oneshot::Sender<ActionOutcome>,
+    },
+    Heartbeat {
+        private_id: Uuid,
+        reply: oneshot::Sender<ActionOutcome>,
+    },
+    Connect {
+        private_id: Uuid,
+        kick_tx: oneshot::Sender<()>,
+        reply: oneshot::Sender<Option<Uuid>>,
+    },
+    Disconnect {
+        private_id: Uuid,
+        connection_id: Uuid,
+    },
+    // 期限が来たプレイヤーの確認。game::session::heartbeatから送られる
+    CheckPlayer {
+        private_id: Uuid,
+        check: PlayerCheck,
+    },
+    Snapshot {
+        reply: oneshot::Sender<GameSessionSnapshot>,
+    },
+    // 上の命令で表せない読み書きは、関数として送る
+    Inspect(Inspection),
+    Update(Update),
+    Close,
+}
+
+/// A way to talk to the task that owns a room. The room stops when it is closed or when
+/// every handle is dropped.
+#[derive(Debug, Clone)]
+pub struct RoomHandle {
+    command_tx: mpsc::Sender<RoomCommand>,
+}
+
+/// Starts the task that owns `session`, and returns the handle to it.
+/// The room removes itself once it has had no players for `room.empty_room_timeout`.
+pub fn spawn_room(session: GameSession) -> RoomHandle {
+    let empty_room_timeout = Duration::from_secs(config::get().room.empty_room_timeout);
+    spawn(
+        session,
+        (!empty_room_timeout.is_zero()).then_some(empty_room_timeout),
+    )
+}
+
+/// Starts the task that owns `session`, and keeps the room even while it is empty.
+#[cfg(debug_assertions)]
+pub fn spawn_permanent_room(session: GameSession) -> RoomHandle {
+    spawn(session, None)
+}
+
+fn spawn(session: GameSession, empty_room_timeout: Option<Duration>) -> RoomHandle {
+    let (command_tx, command_rx) = mpsc::channel(COMMAND_BUFFER);
+    tokio::spawn(run_room(session, command_rx, empty_room_timeout));
+    RoomHandle { command_tx }
+}
+
+async fn run_room(
+    mut session: GameSession,
+    mut command_rx: mpsc::Receiver<RoomCommand>,
+    empty_room_timeout: Option<Duration>,
+) {
+    // 作られたまま誰も参加しなかった部屋も、いずれ片付ける
+    let mut empty_since = session.players.is_empty().then(Instant::now);
+    loop {
+        let removal_deadline = empty_room_timeout.zip(empty_since);
+        let wait_for_removal = async {
+            match removal_deadline {
+                Some((timeout, empty_since)) => time::sleep_until(empty_since + timeout).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            command = command_rx.recv() => {
+                let Some(command) = command else {
+                    break;
+                };
+                if !handle_command(&mut session, command) {
+                    break;
+                }
+                if !session.players.is_empty() {
+                    empty_since = None;
+                } else if empty_since.is_none() {
+                    empty_since = Some(Instant::now());
+                }
+            }
+            _ = wait_for_removal => {
+                // 既に届いている命令には、部屋が閉じられた時と同じくNoneが返る
+                remove_room(session.room_id);
+                notify_lobby();
+                break;
+            }
+        }
+    }
+    // 終了時に保存される部屋はここを通らないので、再起動をまたいで対局を続けられる
+    session.abort_game();
+    // ここでGameSessionがdropし、接続中のWebSocketには部屋が閉じられたことが伝わる
+}
+
+impl RoomCommand {
+    // 席の数やホスト、フェーズを変えうる命令。駒の操作や心拍では、一覧の見た目は変わらない
+    fn can_change_listing(&self) -> bool {
+        matches!(
+            self,
+            Self::Join { .. } | Self::Leave { .. } | Self::CheckPlayer { .. } | Self::Update(_)
+        )
+    }
+}
+
+// 部屋の一覧に影響する変更は、全てここを通る
+fn handle_command(session: &mut GameSession, command: RoomCommand) -> bool {
+    if !command.can_change_listing() {
+        return apply_command(session, command);
+    }
+    let listing = get_listing(session.room_id, session);
+    let is_open = apply_command(session, command);
+    let is_listing_changed = match (&listing, &get_listing(session.room_id, session)) {
+        (Some(before), Some(after)) => !before.is_same_listing(after),
+        (before, after) => before.is_some() != after.is_some(),
+    };
+    if is_listing_changed {
+        notify_lobby();
+    }
+    is_open
+}
+
+// 命令は届いた順に一つずつ処理されるので、同じ部屋の中での出来事の順番は常に決まっている
+// 返信を待たずに諦めた相手もいるので、返信の失敗は無視する
+fn apply_command(session: &mut GameSession, command: RoomCommand) -> bool {
+    match command {
+        RoomCommand::Join {
+            side,
+            token,
+            identity,
+            requested_name,
+            reply,
+        } => {
+            let result = session
+                .join_player(side, token, &identity, requested_name)
+                .map(|private_id| {
+                    let player = &session.players[&private_id];
+                    JoinedPlayer {
+                        private_id,
+                        public_id: player.public_id,
+                        side: player.side,
+                        name: player.name.clone(),
+                    }
+                });
+            let _ = reply.send(result);
+        }
+        RoomCommand::Leave { private_id, reply } => {
+            let _ = reply.send(session.remove_player(private_id));
+        }
+        RoomCommand::Select {
+            private_id,
+            position,
+            reply,
+        } => {
+            // プレイヤーからのメッセージは全て、生きている証拠として扱う
+            let outcome = if !session.update_heartbeat(private_id) {
+                ActionOutcome::NotInRoom
+            } else if session.select_piece(private_id, position).is_err() {
+                ActionOutcome::Rejected
+            } else {
+                ActionOutcome::Accepted
+            };
+            let _ = reply.send(outcome);
+        }
+        RoomCommand::Move {
+            private_id,
+            from,
+            to,
+            reply,
+        } => {
+            let outcome = if !session.update_heartbeat(private_id) {
+                ActionOutcome::NotInRoom
+            } else if session.move_piece(private_id, from, to).is_err() {
+                ActionOutcome::Rejected
+            } else {
+                ActionOutcome::Accepted
+            };
+            let _ = reply.send(outcome);
+        }
+        RoomCommand::Heartbeat { private_id, reply } => {
+            let outcome = if session.update_heartbeat(private_id) {
+                ActionOutcome::Accepted
+            } else {
+                ActionOutcome::NotInRoom
+            };
+            let _ = reply.send(outcome);
+        }
+        RoomCommand::Connect {
+            private_id,
+            kick_tx,
+            reply,
+        } => {
+            let _ = reply.send(session.connect_player(private_id, kick_tx));
+        }
+        RoomCommand::Disconnect {
+            private_id,
+            connection_id,
+        } => {
+            session.disconnect_player(private_id, connection_id);
+        }
+        RoomCommand::CheckPlayer { private_id, check } => {
+            match check {
+                PlayerCheck::Heartbeat => session.check_heartbeat(private_id),
+                PlayerCheck::Reconnect => session.check_reconnect(private_id),
+            };
+        }
+        RoomCommand::Snapshot { reply } => {
+            let _ = reply.send(session.to_snapshot());
+        }
+        RoomCommand::Inspect(inspect) => inspect(session),
+        RoomCommand::Update(update) => update(session),
+        RoomCommand::Close => return false,
+    }
+    true
+}
+
+impl RoomHandle {
+    /// Returns `false` if the room has stopped, for example because it was closed.
+    pub fn is_open(&self) -> bool {
+        !self.command_tx.is_closed()
+    }
+
+    // 部屋が閉じられていて命令を届けられない場合や、返信の前に閉じられた場合はNone
+    async fn request<R, F>(&self, make_command: F) -> Option<R>
+    where
+        F: FnOnce(oneshot::Sender<R>) -> RoomCommand,
+    {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.command_tx.send(make_command(reply_tx)).await.ok()?;
+        reply_rx.await.ok()
+    }
+
+    pub async fn join(
+        &self,
+        side: Side,
+        token: Uuid,
+        identity: Identity,
+        requested_name: Option<String>,
+    ) -> Option<Result<JoinedPlayer, JoinRejection>> {
+        self.request(|reply| RoomCommand::Join {
+            side,
+            token,
+            identity,
+            requested_name,
+            reply,
+        })
+        .await
+    }
+
+    /// Returns `Some(false)` if the player is not in the room.
+    pub async fn leave(&self, private_id: Uuid) -> Option<bool> {
+        self.request(|reply| RoomCommand::Leave { private_id, reply })
+            .await
+    }
+
+    pub async fn select_piece(
+        &self,
+        private_id: Uuid,
+        position: Position,
+    ) -> Option<ActionOutcome> {
+        self.request(|reply| RoomCommand::Select {
+            private_id,
+            position,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn move_piece(
+        &self,
+        private_id: Uuid,
+        from: Position,
+        to: Position,
+    ) -> Option<ActionOutcome> {
+        self.request(|reply| RoomCommand::Move {
+            private_id,
+            from,
+            to,
+            reply,
+        })
+        .await
+    }
+
+    pub async fn heartbeat(&self, private_id: Uuid) -> Option<ActionOutcome> {
+        self.request(|reply| RoomCommand::Heartbeat { private_id, reply })
+            .await
+    }
+
+    /// Binds a WebSocket connection to the player, and returns its ID.
+    /// Returns `Some(None)` if the player is not in the room.
+    pub async fn connect(
+        &self,
+        private_id: Uuid,
+        kick_tx: oneshot::Sender<()>,
+    ) -> Option<Option<Uuid>> {
+        self.request(|reply| RoomCommand::Connect {
+            private_id,
+            kick_tx,
+            reply,
+        })
+        .await
+    }
+
+    /// Tells the room that the connection closed, without waiting.
+    /// If the room is busy, the command is sent from another task once there is room for it.
+    pub fn disconnect(&self, private_id: Uuid, connection_id: Uuid) {
+        let command = RoomCommand::Disconnect {
+            private_id,
+            connection_id,
+        };
+        // 接続ごとに識別されるので、後から届いても他の接続の命令と入れ違うことはない
+        if let Err(mpsc::error::TrySendError::Full(command)) = self.command_tx.try_send(command) {
+            // 終了中でランタイムが使えない場合は、部屋も保存されるだけなので送らなくてよい
+            let Ok(runtime) = runtime::Handle::try_current() else {
+                return;
+            };
+            let command_tx = self.command_tx.clone();
+            runtime.spawn(async move {
+                let _ = command_tx.send(command).await;
+            });
+        }
+    }
+
+    /// Asks the room to run `check` on the player without waiting.
+    /// Returns `false` if the room is closed or too busy to take the command now.
+    pub fn check_player(&self, private_id: Uuid, check: PlayerCheck) -> bool {
+        self.command_tx
+            .try_send(RoomCommand::CheckPlayer { private_id, check })
+            .is_ok()
+    }
+
+    pub async fn snapshot(&self) -> Option<GameSessionSnapshot> {
+        self.request(|reply| RoomCommand::Snapshot { reply }).await
+    }
+
+    /// Runs `f` on the room without changing it, and returns its result.
+    pub async fn inspect<R, F>(&self, f: F) -> Option<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&GameSession) -> R + Send + 'static,
+    {
+        self.request(|reply| {
+            RoomCommand::Inspect(Box::new(move |session| {
+                let _ = reply.send(f(session));
+            }))
+        })
+        .await
+    }
+
+    /// Runs `f` on the room, and returns its result.
+    pub async fn update<R, F>(&self, f: F) -> Option<R>
+    where
+        R: Send + 'static,
+        F: FnOnce(&mut GameSession) -> R + Send + 'static,
+    {
+        self.request(|reply| {
+            RoomCommand::Update(Box::new(move |session| {
+                let _ = reply.send(f(session));
+            }))
+        })
+        .await
+    }
+
+    /// Stops the room after the commands already sent to it.
+    pub async fn close(&self) {
+        let _ = self.command_tx.send(RoomCommand::Close).await;
+    }
+}
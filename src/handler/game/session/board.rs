@@ -0,0 +1,234 @@
+// 盤面のルールだけを扱う関数を置いています。
+// プレイヤーや手番の担当者のことは知らないので、対局の記録から局面を再現する際にも使えます。
+
+use super::{
+    super::INITIAL_NUMBER, GameSessionBoardStyle, MoveRejection, PieceData, Position, Side,
+};
+
+pub type Pieces = Vec<Vec<Option<PieceData>>>;
+
+/// How a move that followed the rules left the turn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MoveOutcome {
+    /// The same side must keep capturing.
+    Continue,
+    TurnFinished,
+}
+
+pub fn create_initial_pieces(board_size: usize, board_style: GameSessionBoardStyle) -> Pieces {
+    let mut pieces = vec![vec![None; board_size]; board_size];
+    match board_style {
+        GameSessionBoardStyle::Checker => {
+            for i in 0..3 {
+                for j in 0..(board_size / 2) {
+                    let top_square = j * 2 + ((board_size - i) % 2);
+                    let bottom_square = j * 2 + ((i + 1) % 2);
+                    if top_square < board_size {
+                        pieces[i][top_square] = Some(PieceData {
+                            side: Side::Top,
+                            number: INITIAL_NUMBER,
+                        });
+                    }
+                    if bottom_square < board_size {
+                        pieces[board_size - (i + 1)][bottom_square] = Some(PieceData {
+                            side: Side::Bottom,
+                            number: INITIAL_NUMBER,
+                        });
+                    }
+                }
+            }
+        }
+        GameSessionBoardStyle::Chess => {
+            for i in 0..2 {
+                pieces[i].fill(Some(PieceData {
+                    side: Side::Top,
+                    number: INITIAL_NUMBER,
+                }));
+                pieces[board_size - (i + 1)].fill(Some(PieceData {
+                    side: Side::Bottom,
+                    number: INITIAL_NUMBER,
+                }));
+            }
+        }
+    }
+    pieces
+}
+
+pub fn is_on_board(pieces: &[Vec<Option<PieceData>>], (x, y): Position) -> bool {
+    x < pieces.len() && y < pieces.len()
+}
+
+pub fn is_any_piece_still_movable(pieces: &[Vec<Option<PieceData>>], side: Side) -> bool {
+    let board_size = pieces.len();
+    for (y, row) in pieces.iter().enumerate() {
+        for (x, piece) in row.iter().enumerate() {
+            if !piece.is_some_and(|p| p.side == side) {
+                continue;
+            }
+            let number = piece.unwrap().number;
+            // x
+            //   o
+            //
+            if x >= 2
+                && y >= 2
+                && pieces[y - 1][x - 1].is_some_and(|p| side != p.side && number > p.number)
+                && pieces[y - 2][x - 2].is_none()
+            {
+                return true;
+            }
+            //     x
+            //   o
+            //
+            if x <= board_size - 3
+                && y >= 2
+                && pieces[y - 1][x + 1].is_some_and(|p| side != p.side && number > p.number)
+                && pieces[y - 2][x + 2].is_none()
+            {
+                return true;
+            }
+            //
+            //   o
+            // x
+            if x >= 2
+                && y <= board_size - 3
+                && pieces[y + 1][x - 1].is_some_and(|p| side != p.side && number > p.number)
+                && pieces[y + 2][x - 2].is_none()
+            {
+                return true;
+            }
+            //
+            //   o
+            //     x
+            if x <= board_size - 3
+                && y <= board_size - 3
+                && pieces[y + 1][x + 1].is_some_and(|p| side != p.side && number > p.number)
+                && pieces[y + 2][x + 2].is_none()
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+pub fn has_any_piece(pieces: &[Vec<Option<PieceData>>], side: Side) -> bool {
+    pieces
+        .iter()
+        .flatten()
+        .any(|piece| piece.is_some_and(|p| p.side == side))
+}
+
+/// 取る手に加えて、空いているマスへの移動や、自分の駒への合流ができるかも調べます。
+pub fn can_side_move(pieces: &[Vec<Option<PieceData>>], side: Side) -> bool {
+    if is_any_piece_still_movable(pieces, side) {
+        return true;
+    }
+    let board_size = pieces.len() as isize;
+    for (y, row) in pieces.iter().enumerate() {
+        for (x, piece) in row.iter().enumerate() {
+            let Some(piece) = piece.filter(|p| p.side == side) else {
+                continue;
+            };
+            for (dx, dy) in [(-1, -1), (1, -1), (-1, 1), (1, 1)] {
+                let (new_x, new_y) = (x as isize + dx, y as isize + dy);
+                if new_x < 0 || new_y < 0 || new_x >= board_size || new_y >= board_size {
+                    continue;
+                }
+                match pieces[new_y as usize][new_x as usize] {
+                    None => return true,
+                    Some(p) if p.side == side && piece.number > 2 => return true,
+                    _ => {}
+                }
+            }
+        }
+    }
+    false
+}
+
+/// Returns the winner if the game is over with `current_turn` to move.
+pub fn judge_winner(pieces: &[Vec<Option<PieceData>>], current_turn: Side) -> Option<Side> {
+    if !has_any_piece(pieces, Side::Top) {
+        Some(Side::Bottom)
+    } else if !has_any_piece(pieces, Side::Bottom) {
+        Some(Side::Top)
+    } else if !can_side_move(pieces, current_turn) {
+        // 次に動かせる駒がなくなった側の負け
+        Some(current_turn.opposite())
+    } else {
+        None
+    }
+}
+
+/// Moves a piece of `side` if the rules allow it. Whose turn it is must be checked by the caller.
+pub fn apply_move(
+    pieces: &mut Pieces,
+    side: Side,
+    old_position: Position,
+    new_position: Position,
+) -> Result<MoveOutcome, MoveRejection> {
+    if !is_on_board(pieces, old_position) || !is_on_board(pieces, new_position) {
+        return Err(MoveRejection::OutOfBoard);
+    }
+    let ((old_x, old_y), (new_x, new_y)) = (old_position, new_position);
+    let moving_piece_number = match pieces[old_y][old_x] {
+        Some(piece) if piece.side == side => piece.number,
+        _ => return Err(MoveRejection::NotYourPiece),
+    };
+    let destination_piece = pieces[new_y][new_x];
+    let x_diff = old_x.abs_diff(new_x);
+    let y_diff = old_y.abs_diff(new_y);
+    // 敵の駒を取る
+    if x_diff == 2 && y_diff == 2 {
+        if destination_piece.is_some() {
+            return Err(MoveRejection::IllegalMove);
+        }
+        let between_x = {
+            if old_x > new_x {
+                old_x - 1
+            } else {
+                old_x + 1
+            }
+        };
+        let between_y = {
+            if old_y > new_y {
+                old_y - 1
+            } else {
+                old_y + 1
+            }
+        };
+        let between_piece = pieces[between_y][between_x];
+        if between_piece.is_none_or(|p| moving_piece_number <= p.number) {
+            return Err(MoveRejection::IllegalMove);
+        }
+        pieces[new_y][new_x] = Some(PieceData {
+            side,
+            number: ((moving_piece_number as f32) * (2.0 / 3.0)) as u8,
+        });
+        pieces[between_y][between_x] = None;
+        pieces[old_y][old_x] = None;
+        if is_any_piece_still_movable(pieces, side) {
+            Ok(MoveOutcome::Continue)
+        } else {
+            Ok(MoveOutcome::TurnFinished)
+        }
+    } else if x_diff == 1 && y_diff == 1 {
+        if is_any_piece_still_movable(pieces, side) {
+            return Err(MoveRejection::IllegalMove);
+        }
+        match destination_piece {
+            Some(piece) if piece.side == side && moving_piece_number > 2 => {
+                pieces[new_y][new_x].as_mut().unwrap().number += moving_piece_number.div_ceil(2);
+                pieces[old_y][old_x].as_mut().unwrap().number = moving_piece_number / 2;
+                Ok(MoveOutcome::TurnFinished)
+            }
+            None => {
+                pieces[new_y][new_x] = pieces[old_y][old_x];
+                pieces[old_y][old_x] = None;
+                Ok(MoveOutcome::TurnFinished)
+            }
+            _ => Err(MoveRejection::IllegalMove),
+        }
+    } else {
+        Err(MoveRejection::IllegalMove)
+    }
+}
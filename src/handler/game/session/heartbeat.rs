@@ -0,0 +1,103 @@
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    future,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use tokio::{sync::mpsc, time};
+use uuid::Uuid;
+
+use crate::util::shutdown;
+
+use super::map::get_room;
+
+// 部屋のタスクが混んでいて命令を受け取れなかった場合に、もう一度確認するまでの時間
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// What the room has to check about the player when the deadline passes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PlayerCheck {
+    /// Whether the player stopped sending heartbeats.
+    Heartbeat,
+    /// Whether the player came back after their last connection closed.
+    Reconnect,
+}
+
+/// The time at which a player has to be checked.
+// BinaryHeapでは期限の早い順に並ぶように、atを先頭のフィールドにしている
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Deadline {
+    at: Instant,
+    room_id: Uuid,
+    private_id: Uuid,
+    check: PlayerCheck,
+}
+
+fn get_heartbeat_sweeper() -> &'static mpsc::UnboundedSender<Deadline> {
+    static HEARTBEAT_SWEEPER: OnceLock<mpsc::UnboundedSender<Deadline>> = OnceLock::new();
+    HEARTBEAT_SWEEPER.get_or_init(|| {
+        let (deadline_tx, deadline_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_sweeper(deadline_rx));
+        deadline_tx
+    })
+}
+
+/// Asks the sweeper to have the room run `check` on the player at `at`.
+/// Each player should have one pending heartbeat check at a time; the room schedules
+/// the next one when the check runs.
+pub fn schedule(room_id: Uuid, private_id: Uuid, check: PlayerCheck, at: Instant) {
+    let _ = get_heartbeat_sweeper().send(Deadline {
+        at,
+        room_id,
+        private_id,
+        check,
+    });
+}
+
+/// Sleeps until the earliest deadline, and only then sends the room a command.
+/// Rooms whose players keep sending heartbeats are touched once per threshold,
+/// and rooms without players are never touched.
+async fn run_sweeper(mut deadline_rx: mpsc::UnboundedReceiver<Deadline>) {
+    let mut deadlines = BinaryHeap::<Reverse<Deadline>>::new();
+    let mut shutdown_rx = shutdown::subscribe();
+    loop {
+        let next_deadline = deadlines.peek().map(|Reverse(deadline)| deadline.at);
+        let wait_for_next_deadline = async {
+            match next_deadline {
+                Some(at) => time::sleep_until(at.into()).await,
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            deadline = deadline_rx.recv() => {
+                let Some(deadline) = deadline else {
+                    break;
+                };
+                deadlines.push(Reverse(deadline));
+            }
+            _ = wait_for_next_deadline => {
+                let now = Instant::now();
+                while let Some(Reverse(deadline)) = deadlines.peek().copied() {
+                    if deadline.at > now {
+                        break;
+                    }
+                    deadlines.pop();
+                    // 部屋が閉じられていれば、そのプレイヤーの確認も不要になる
+                    let Some(room) = get_room(deadline.room_id) else {
+                        continue;
+                    };
+                    if !room.check_player(deadline.private_id, deadline.check) && room.is_open() {
+                        deadlines.push(Reverse(Deadline {
+                            at: now + RETRY_DELAY,
+                            ..deadline
+                        }));
+                    }
+                }
+            }
+            // シャットダウン中に席を外されると、保存される状態から消えてしまう
+            _ = shutdown::wait(&mut shutdown_rx) => break,
+        }
+    }
+}
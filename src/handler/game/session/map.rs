@@ -1,16 +1,24 @@
-use std::{
-    collections::HashMap,
-    ops::{Deref, DerefMut},
-    sync::OnceLock,
-};
+use std::{collections::HashMap, sync::OnceLock};
+
+use futures_util::future;
+use parking_lot::RwLock;
+#[cfg(debug_assertions)]
+use uuid::uuid;
+use uuid::Uuid;
 
-use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
-use uuid::{uuid, Uuid};
+use crate::config;
 
-use super::{GameSession, GameSessionBoardStyle, GameSessionConfig};
+#[cfg(debug_assertions)]
+use super::{actor::spawn_permanent_room, GameSessionBoardStyle, GameSessionConfig};
+use super::{
+    actor::{spawn_room, RoomHandle},
+    GameSession, Side,
+};
 
-pub fn get_game_session_map() -> &'static RwLock<HashMap<Uuid, GameSession>> {
-    static GAME_SESSION_MAP: OnceLock<RwLock<HashMap<Uuid, GameSession>>> = OnceLock::new();
+/// Returns the map from room IDs to the tasks that own the rooms.
+/// The lock is only held to add, remove or look up rooms, never while a room is working.
+pub fn get_game_session_map() -> &'static RwLock<HashMap<Uuid, RoomHandle>> {
+    static GAME_SESSION_MAP: OnceLock<RwLock<HashMap<Uuid, RoomHandle>>> = OnceLock::new();
     GAME_SESSION_MAP.get_or_init(|| {
         RwLock::new(
             #[cfg(not(debug_assertions))]
@@ -24,24 +32,26 @@ pub fn get_game_session_map() -> &'static RwLock<HashMap<Uuid, GameSession>> {
                 HashMap::from([
                     (
                         debug_room_id_1,
-                        GameSession::new(
+                        spawn_permanent_room(GameSession::new(
                             debug_room_id_1,
                             GameSessionConfig {
                                 team_player_limit: usize::MAX,
                                 ..Default::default()
                             },
-                        ),
+                            None,
+                        )),
                     ),
                     (
                         debug_room_id_2,
-                        GameSession::new(
+                        spawn_permanent_room(GameSession::new(
                             debug_room_id_2,
                             GameSessionConfig {
                                 board_style: GameSessionBoardStyle::Chess,
                                 team_player_limit: usize::MAX,
                                 ..Default::default()
                             },
-                        ),
+                            None,
+                        )),
                     ),
                 ])
             },
@@ -49,54 +59,93 @@ pub fn get_game_session_map() -> &'static RwLock<HashMap<Uuid, GameSession>> {
     })
 }
 
-pub struct ReadLockedSession<'a> {
-    lock: RwLockReadGuard<'a, HashMap<Uuid, GameSession>>,
-    room_id: Uuid,
+/// Returns `None` if the room doesn't exist.
+/// Rooms can be closed at any time by the admin API, so every request to the handle can still
+/// come back empty, even right after `room_existence_check`.
+pub fn get_room(room_id: Uuid) -> Option<RoomHandle> {
+    get_game_session_map()
+        .read()
+        .get(&room_id)
+        .filter(|room| room.is_open())
+        .cloned()
 }
 
-impl Deref for ReadLockedSession<'_> {
-    type Target = GameSession;
+/// Returns every open room.
+pub fn get_rooms() -> Vec<(Uuid, RoomHandle)> {
+    get_game_session_map()
+        .read()
+        .iter()
+        .filter(|(_, room)| room.is_open())
+        .map(|(room_id, room)| (*room_id, room.clone()))
+        .collect()
+}
 
-    fn deref(&self) -> &Self::Target {
-        self.lock.get(&self.room_id).unwrap()
-    }
+/// Starts a task for `session` and adds it as a room, regardless of `limit.max_rooms`.
+pub fn insert_room(room_id: Uuid, session: GameSession) {
+    get_game_session_map()
+        .write()
+        .insert(room_id, spawn_room(session));
 }
 
-pub struct WriteLockedSession<'a> {
-    lock: RwLockWriteGuard<'a, HashMap<Uuid, GameSession>>,
-    room_id: Uuid,
+fn is_below_room_limit(room_count: usize) -> bool {
+    let max_rooms = config::get().limit.max_rooms;
+    max_rooms == 0 || room_count < max_rooms
 }
 
-impl Deref for WriteLockedSession<'_> {
-    type Target = GameSession;
+/// Returns whether another room can be created without exceeding `limit.max_rooms`.
+pub fn has_room_capacity() -> bool {
+    is_below_room_limit(get_game_session_map().read().len())
+}
 
-    fn deref(&self) -> &Self::Target {
-        self.lock.get(&self.room_id).unwrap()
+/// Adds a new room. Returns `false` and drops `session` if there are already
+/// `limit.max_rooms` rooms.
+pub fn try_insert_room(room_id: Uuid, session: GameSession) -> bool {
+    let mut game_session_map = get_game_session_map().write();
+    if !is_below_room_limit(game_session_map.len()) {
+        return false;
     }
+    game_session_map.insert(room_id, spawn_room(session));
+    true
 }
 
-impl DerefMut for WriteLockedSession<'_> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        self.lock.get_mut(&self.room_id).unwrap()
-    }
+/// Removes the room from the map without stopping its task.
+/// Called by the room itself when it stops on its own.
+pub fn remove_room(room_id: Uuid) {
+    get_game_session_map().write().remove(&room_id);
 }
 
-/// # The returned value will panic if room doesn't exist.
-/// Make sure room is created before this function.
-#[inline(always)]
-pub fn get_immutable_session(room_id: Uuid) -> ReadLockedSession<'static> {
-    ReadLockedSession {
-        lock: get_game_session_map().read(),
-        room_id,
-    }
+/// Removes the room and stops its task. Returns `false` if the room doesn't exist.
+pub async fn close_room(room_id: Uuid) -> bool {
+    let Some(room) = get_game_session_map().write().remove(&room_id) else {
+        return false;
+    };
+    room.close().await;
+    true
 }
 
-/// # The returned value will panic if room doesn't exist.
-/// Make sure room is created before this function.
-#[inline(always)]
-pub fn get_mutable_session(room_id: Uuid) -> WriteLockedSession<'static> {
-    WriteLockedSession {
-        lock: get_game_session_map().write(),
-        room_id,
+/// Returns the number of rooms and the number of players on each side across all rooms.
+pub async fn count_rooms_and_players() -> (usize, [(Side, usize); 2]) {
+    let counts = future::join_all(get_rooms().into_iter().map(|(_, room)| async move {
+        room.inspect(|session| {
+            let mut counts = [0, 0];
+            for player in session.players.values() {
+                match player.side {
+                    Side::Top => counts[0] += 1,
+                    Side::Bottom => counts[1] += 1,
+                }
+            }
+            counts
+        })
+        .await
+    }))
+    .await;
+    let mut room_count = 0;
+    let mut player_counts = [(Side::Top, 0), (Side::Bottom, 0)];
+    // 数えている間に閉じられた部屋は数えない
+    for [top, bottom] in counts.into_iter().flatten() {
+        room_count += 1;
+        player_counts[0].1 += top;
+        player_counts[1].1 += bottom;
     }
+    (room_count, player_counts)
 }
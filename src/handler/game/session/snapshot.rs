@@ -0,0 +1,138 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Instant, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::{
+    super::rating::RatedPlayer, GamePhase, GameSession, GameSessionConfig, PieceData, PlayerData,
+    Position, Side,
+};
+
+/// サーバーの再起動をまたいで部屋を引き継ぐために、GameSessionを保存可能な形にしたものです。
+/// broadcastのチャンネルのような実行時にしか意味を持たないものは含まれません。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GameSessionSnapshot {
+    config: GameSessionConfig,
+    players: HashMap<Uuid, PlayerSnapshot>,
+    pieces: Vec<Vec<Option<PieceData>>>,
+    phase: GamePhase,
+    host: Option<Uuid>,
+    is_locked: bool,
+    winner: Option<Side>,
+    rematch_requests: HashSet<Side>,
+    first_turn: Side,
+    current_turn: Side,
+    next_join_order: u64,
+    last_movers: HashMap<Side, u64>,
+    // この項目がない古いファイルから復元した部屋は、復元した時刻に作られたものとして扱う
+    #[serde(default = "SystemTime::now")]
+    created_at: SystemTime,
+    #[serde(default)]
+    rated_lineup: Vec<RatedPlayer>,
+    #[serde(default)]
+    game_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PlayerSnapshot {
+    public_id: Uuid,
+    name: String,
+    selecting_piece: Option<Position>,
+    is_ready: bool,
+    side: Side,
+    token: Uuid,
+    join_order: u64,
+}
+
+impl GameSession {
+    pub fn to_snapshot(&self) -> GameSessionSnapshot {
+        GameSessionSnapshot {
+            config: self.config,
+            players: self
+                .players
+                .iter()
+                .map(|(private_id, data)| {
+                    (
+                        *private_id,
+                        PlayerSnapshot {
+                            public_id: data.public_id,
+                            name: data.name.clone(),
+                            selecting_piece: data.selecting_piece,
+                            is_ready: data.is_ready,
+                            side: data.side,
+                            token: data.token,
+                            join_order: data.join_order,
+                        },
+                    )
+                })
+                .collect(),
+            pieces: self.pieces.clone(),
+            phase: self.phase,
+            host: self.host,
+            is_locked: self.is_locked,
+            winner: self.winner,
+            rematch_requests: self.rematch_requests.clone(),
+            first_turn: self.first_turn,
+            current_turn: self.current_turn,
+            next_join_order: self.next_join_order,
+            last_movers: self.last_movers.clone(),
+            created_at: self.created_at,
+            rated_lineup: self.rated_lineup.clone(),
+            game_id: self.game_id,
+        }
+    }
+
+    /// Restores a session saved by `to_snapshot`.
+    /// Heartbeats and the reconnect grace period restart from now, so players get the full time
+    /// to reconnect.
+    pub fn from_snapshot(room_id: Uuid, snapshot: GameSessionSnapshot) -> Self {
+        let mut session = Self::new(room_id, snapshot.config, snapshot.host);
+        let now = Instant::now();
+        session.players = snapshot
+            .players
+            .into_iter()
+            .map(|(private_id, player)| {
+                (
+                    private_id,
+                    PlayerData {
+                        public_id: player.public_id,
+                        name: player.name,
+                        selecting_piece: player.selecting_piece,
+                        is_inactive: false,
+                        // 再起動の前の接続は全て閉じている
+                        is_disconnected: true,
+                        is_ready: player.is_ready,
+                        side: player.side,
+                        last_heartbeat: now,
+                        disconnected_at: Some(now),
+                        token: player.token,
+                        join_order: player.join_order,
+                    },
+                )
+            })
+            .collect();
+        for private_id in session.get_player_ids() {
+            session.schedule_heartbeat_check(private_id, now);
+            session.schedule_reconnect_check(private_id, now);
+        }
+        session.pieces = snapshot.pieces;
+        session.phase = snapshot.phase;
+        session.is_locked = snapshot.is_locked;
+        session.winner = snapshot.winner;
+        session.rematch_requests = snapshot.rematch_requests;
+        session.first_turn = snapshot.first_turn;
+        session.current_turn = snapshot.current_turn;
+        session.next_join_order = snapshot.next_join_order;
+        session.last_movers = snapshot.last_movers;
+        session.created_at = snapshot.created_at;
+        session.rated_lineup = snapshot.rated_lineup;
+        session.game_id = snapshot.game_id;
+        session.announced_turn_player = session
+            .get_turn_player()
+            .map(|private_id| session.get_public_id(private_id));
+        session
+    }
+}
@@ -3,7 +3,14 @@ use uuid::Uuid;
 
 use crate::util::deser_utils;
 
-use super::session::{PieceData, PlayerData, Position, Side};
+use super::{
+    archive::{GameRecord, RecordedMove},
+    rating::RatingHistoryEntry,
+    session::{
+        GamePhase, GameSessionBoardStyle, GameSessionConfig, PieceData, PlayerData, Position, Side,
+        TeamMode, Visibility,
+    },
+};
 
 // HTTP
 
@@ -14,7 +21,7 @@ pub struct HttpPieceData {
 }
 
 impl HttpPieceData {
-    pub fn from_piece_data(piece_data: &Vec<Vec<Option<PieceData>>>, side: Side) -> Vec<Self> {
+    pub fn from_piece_data(piece_data: &[Vec<Option<PieceData>>], side: Side) -> Vec<Self> {
         let mut v = Vec::new();
         for (y, row) in piece_data.iter().enumerate() {
             for (x, piece) in row.iter().enumerate() {
@@ -34,13 +41,42 @@ impl HttpPieceData {
 pub struct RoomData {
     pub room_id: Uuid,
     pub board_size: usize,
+    pub team_mode: TeamMode,
+    pub rated: bool,
+    /// 現在の(または最後の)対局の記録のID
+    pub game_id: Option<Uuid>,
+    pub phase: GamePhase,
+    pub winner: Option<Side>,
+    pub host: Option<Uuid>,
+    pub is_locked: bool,
     pub current_turn: Side,
+    pub current_turn_player: Option<Uuid>,
     pub top_players: Vec<PlayerData>,
     pub top_pieces: Vec<HttpPieceData>,
     pub bottom_players: Vec<PlayerData>,
     pub bottom_pieces: Vec<HttpPieceData>,
 }
 
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct NewRoomQuery {
+    pub team_mode: Option<TeamMode>,
+    pub swap_sides: Option<bool>,
+    pub visibility: Option<Visibility>,
+    pub rated: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ResultData {
+    pub success: bool,
+    pub message: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JoinRequest {
+    pub side: Side,
+    pub name: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct CreateUserData {
     pub success: bool,
@@ -51,6 +87,255 @@ pub struct CreateUserData {
     pub name: Option<String>,
 }
 
+// Matchmaking
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct MatchmakingRequest {
+    pub board_size: Option<usize>,
+    pub board_style: Option<GameSessionBoardStyle>,
+    pub rated: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchmakingJoinData {
+    pub success: bool,
+    pub message: Option<&'static str>,
+    pub ticket_id: Option<Uuid>,
+}
+
+/// 待っている間は`waiting`、成立すると`matched`になり、部屋に参加するための情報が含まれます。
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TicketStatus {
+    Waiting,
+    Matched {
+        room_id: Uuid,
+        side: Side,
+        private_id: Uuid,
+        public_id: Uuid,
+    },
+    TimedOut,
+    Cancelled,
+}
+
+// Rating
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerProfileData {
+    pub public_id: Uuid,
+    pub name: String,
+    pub rating: i64,
+    pub wins: u32,
+    pub losses: u32,
+    /// 対局数が少なく、まだランキングに載らない
+    pub is_provisional: bool,
+    pub history: Vec<RatingHistoryEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LeaderboardQuery {
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub public_id: Uuid,
+    pub name: String,
+    pub rating: i64,
+    pub wins: u32,
+    pub losses: u32,
+}
+
+// Replay
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplayData {
+    #[serde(flatten)]
+    pub record: GameRecord,
+    pub initial_top_pieces: Vec<HttpPieceData>,
+    pub initial_bottom_pieces: Vec<HttpPieceData>,
+    /// まだ対局が続いていて、手が増える可能性がある
+    pub in_progress: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PositionQuery {
+    /// 省略すると最新の局面
+    pub ply: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PositionData {
+    pub game_id: Uuid,
+    pub ply: usize,
+    pub total_plies: usize,
+    pub current_turn: Side,
+    /// この局面に至った手
+    pub last_move: Option<RecordedMove>,
+    /// この局面で勝敗が決まっていれば、その勝者
+    pub winner: Option<Side>,
+    pub top_pieces: Vec<HttpPieceData>,
+    pub bottom_pieces: Vec<HttpPieceData>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportGameData {
+    pub success: bool,
+    pub message: Option<&'static str>,
+    /// 読み込めなかった場合に、その行や手を示す説明
+    pub detail: Option<String>,
+    pub game_id: Option<Uuid>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+struct RawReplayAction {
+    t: u8,
+    c: Option<usize>,
+}
+
+/// 再生用のWebSocketで、クライアントから送られる操作です。
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(try_from = "RawReplayAction")]
+pub enum ReplayAction {
+    Next,
+    Previous,
+    Seek(usize),
+}
+
+impl TryFrom<RawReplayAction> for ReplayAction {
+    type Error = &'static str;
+
+    fn try_from(value: RawReplayAction) -> Result<Self, Self::Error> {
+        match (value.t, value.c) {
+            (1, _) => Ok(Self::Next),
+            (2, _) => Ok(Self::Previous),
+            (3, Some(ply)) => Ok(Self::Seek(ply)),
+            (3, None) => Err("missing ply"),
+            _ => Err("unknown replay action"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ReplayEvent {
+    Position(PositionData),
+    /// この接続が局面を動かせるかどうか
+    Control(bool),
+}
+
+impl Serialize for ReplayEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("ReplayEvent", 2)?;
+        match *self {
+            Self::Position(ref position) => {
+                state.serialize_field("t", &1)?;
+                state.serialize_field("c", position)?;
+            }
+            Self::Control(is_controller) => {
+                state.serialize_field("t", &2)?;
+                state.serialize_field("c", &is_controller)?;
+            }
+        }
+        state.end()
+    }
+}
+
+// Lobby
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicRoomData {
+    pub room_id: Uuid,
+    pub board_size: usize,
+    pub board_style: GameSessionBoardStyle,
+    pub team_mode: TeamMode,
+    pub team_player_limit: usize,
+    pub rated: bool,
+    pub seats: PlayerCounts,
+    pub host_name: Option<String>,
+    pub age_seconds: u64,
+}
+
+impl PublicRoomData {
+    /// Compares everything but `age_seconds`, which changes every second on its own.
+    pub fn is_same_listing(&self, other: &Self) -> bool {
+        self.room_id == other.room_id
+            && self.board_size == other.board_size
+            && self.board_style == other.board_style
+            && self.team_mode == other.team_mode
+            && self.team_player_limit == other.team_player_limit
+            && self.rated == other.rated
+            && self.seats == other.seats
+            && self.host_name == other.host_name
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LobbyEvent {
+    List(Vec<PublicRoomData>),
+    Update(PublicRoomData),
+    Remove(Uuid),
+}
+
+impl Serialize for LobbyEvent {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut state = serializer.serialize_struct("LobbyEvent", 2)?;
+        match *self {
+            Self::List(ref rooms) => {
+                state.serialize_field("t", &1)?;
+                state.serialize_field("c", rooms)?;
+            }
+            Self::Update(ref room) => {
+                state.serialize_field("t", &2)?;
+                state.serialize_field("c", room)?;
+            }
+            Self::Remove(ref room_id) => {
+                state.serialize_field("t", &3)?;
+                state.serialize_field("c", room_id)?;
+            }
+        }
+        state.end()
+    }
+}
+
+// Admin
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct PlayerCounts {
+    pub top: usize,
+    pub bottom: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminRoomSummary {
+    pub room_id: Uuid,
+    pub config: GameSessionConfig,
+    pub player_counts: PlayerCounts,
+    pub age_seconds: u64,
+    pub phase: GamePhase,
+    pub is_locked: bool,
+}
+
+/// 部屋の詳細です。プレイヤーのprivate_idやtokenは、管理者にも見せません。
+#[derive(Debug, Clone, Serialize)]
+pub struct AdminRoomDetail {
+    pub room_id: Uuid,
+    pub age_seconds: u64,
+    pub config: GameSessionConfig,
+    pub state: RoomData,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NoticeRequest {
+    pub message: String,
+}
+
 // WebSocket
 
 #[derive(Debug, Clone, Copy, Deserialize)]
@@ -59,13 +344,16 @@ pub struct AuthData {
     pub private_id: Uuid,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum WebSocketMessaging {
     HeartbeatAck,
     NotAccepted(PlayerAction),
     SessionExpired,
     GotBinary,
     GotInvalidData,
+    TooManyMessages,
+    // 同じプレイヤーが別の場所から接続したので、この接続は閉じられる
+    ConnectedElsewhere,
 }
 
 impl Serialize for WebSocketMessaging {
@@ -83,7 +371,7 @@ impl Serialize for WebSocketMessaging {
             Self::HeartbeatAck => {
                 state.serialize_field("t", &100)?;
             }
-            Self::NotAccepted(action) => {
+            Self::NotAccepted(ref action) => {
                 state.serialize_field("t", &101)?;
                 state.serialize_field("c", &action)?;
             }
@@ -96,16 +384,32 @@ impl Serialize for WebSocketMessaging {
             Self::GotInvalidData => {
                 state.serialize_field("t", &104)?;
             }
+            Self::TooManyMessages => {
+                state.serialize_field("t", &105)?;
+            }
+            Self::ConnectedElsewhere => {
+                state.serialize_field("t", &106)?;
+            }
         }
         state.end()
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum PlayerAction {
     Heartbeat,
     SelectPiece(Position),
     MovePiece(Position, Position),
+    Rename(String),
+    Ready(bool),
+    RequestRematch,
+    // これより下は部屋のホストのみが実行できる
+    Kick(Uuid),
+    Lock(bool),
+    ChangeSide(Uuid, Side),
+    ResetBoard,
+    TransferHost(Uuid),
+    StartGame,
 }
 
 impl Serialize for PlayerAction {
@@ -114,7 +418,10 @@ impl Serialize for PlayerAction {
         S: serde::Serializer,
     {
         let mut state;
-        if matches!(*self, Self::Heartbeat) {
+        if matches!(
+            *self,
+            Self::Heartbeat | Self::ResetBoard | Self::StartGame | Self::RequestRematch
+        ) {
             state = serializer.serialize_struct("PlayerAction", 1)?;
         } else {
             state = serializer.serialize_struct("PlayerAction", 2)?;
@@ -131,6 +438,39 @@ impl Serialize for PlayerAction {
                 state.serialize_field("t", &2)?;
                 state.serialize_field("c", &(pos1, pos2))?;
             }
+            Self::Rename(ref name) => {
+                state.serialize_field("t", &3)?;
+                state.serialize_field("c", name)?;
+            }
+            Self::Ready(is_ready) => {
+                state.serialize_field("t", &4)?;
+                state.serialize_field("c", &is_ready)?;
+            }
+            Self::Kick(ref public_id) => {
+                state.serialize_field("t", &5)?;
+                state.serialize_field("c", public_id)?;
+            }
+            Self::Lock(is_locked) => {
+                state.serialize_field("t", &6)?;
+                state.serialize_field("c", &is_locked)?;
+            }
+            Self::ChangeSide(ref public_id, ref side) => {
+                state.serialize_field("t", &7)?;
+                state.serialize_field("c", &(public_id, side))?;
+            }
+            Self::ResetBoard => {
+                state.serialize_field("t", &8)?;
+            }
+            Self::TransferHost(ref public_id) => {
+                state.serialize_field("t", &9)?;
+                state.serialize_field("c", public_id)?;
+            }
+            Self::StartGame => {
+                state.serialize_field("t", &10)?;
+            }
+            Self::RequestRematch => {
+                state.serialize_field("t", &11)?;
+            }
         }
         state.end()
     }
@@ -141,13 +481,22 @@ deser_utils::dcwt! {
     tag: "t",
     content: "c",
     with_no_content: [
-        Heartbeat = 99
+        Heartbeat = 99,
+        ResetBoard = 8,
+        StartGame = 10,
+        RequestRematch = 11
     ],
     with_single_content: [
-        Position => SelectPiece(a) = 1
+        Position => SelectPiece(a) = 1,
+        String => Rename(a) = 3,
+        bool => Ready(a) = 4,
+        Uuid => Kick(a) = 5,
+        bool => Lock(a) = 6,
+        Uuid => TransferHost(a) = 9
     ],
     with_tuplelike_content: [
-        (Position, Position) => MovePiece(a, b) = 2
+        (Position, Position) => MovePiece(a, b) = 2,
+        (Uuid, Side) => ChangeSide(a, b) = 7
     ]
 }
 
@@ -159,6 +508,26 @@ pub enum RoomEvent {
     BottomPlayerJoin(String),
     TopPlayerLeave,
     BottomPlayerLeave,
+    PlayerRename(String),
+    TurnPlayer,
+    PlayerReady(bool),
+    GameStart,
+    PlayerKick(Uuid),
+    RoomLock(bool),
+    SideChange(Uuid, Side),
+    BoardReset,
+    HostTransfer(Uuid),
+    GameEnd(Side),
+    RematchRequest,
+    Rematch,
+    // 運営からのお知らせ。RoomEventWithIdのpublic_idはnilになる
+    ServerNotice(String),
+    // heartbeatが途絶えた、または再開したプレイヤー。状態が変わった時にだけ送られる
+    PlayerInactive,
+    PlayerActive,
+    // プレイヤーの最後の接続が閉じた、または閉じた後に再び接続した
+    PlayerDisconnect,
+    PlayerReconnect,
 }
 
 impl Serialize for RoomEvent {
@@ -167,7 +536,20 @@ impl Serialize for RoomEvent {
         S: serde::Serializer,
     {
         let mut state;
-        if matches!(*self, Self::TopPlayerLeave | Self::BottomPlayerLeave) {
+        if matches!(
+            *self,
+            Self::TopPlayerLeave
+                | Self::BottomPlayerLeave
+                | Self::TurnPlayer
+                | Self::GameStart
+                | Self::BoardReset
+                | Self::RematchRequest
+                | Self::Rematch
+                | Self::PlayerInactive
+                | Self::PlayerActive
+                | Self::PlayerDisconnect
+                | Self::PlayerReconnect
+        ) {
             state = serializer.serialize_struct("RoomEvent", 1)?;
         } else {
             state = serializer.serialize_struct("RoomEvent", 2)?;
@@ -195,6 +577,65 @@ impl Serialize for RoomEvent {
             Self::BottomPlayerLeave => {
                 state.serialize_field("t", &6)?;
             }
+            Self::PlayerRename(ref name) => {
+                state.serialize_field("t", &7)?;
+                state.serialize_field("c", name)?;
+            }
+            Self::TurnPlayer => {
+                state.serialize_field("t", &8)?;
+            }
+            Self::PlayerReady(is_ready) => {
+                state.serialize_field("t", &9)?;
+                state.serialize_field("c", &is_ready)?;
+            }
+            Self::GameStart => {
+                state.serialize_field("t", &10)?;
+            }
+            Self::PlayerKick(ref public_id) => {
+                state.serialize_field("t", &11)?;
+                state.serialize_field("c", public_id)?;
+            }
+            Self::RoomLock(is_locked) => {
+                state.serialize_field("t", &12)?;
+                state.serialize_field("c", &is_locked)?;
+            }
+            Self::SideChange(ref public_id, ref side) => {
+                state.serialize_field("t", &13)?;
+                state.serialize_field("c", &(public_id, side))?;
+            }
+            Self::BoardReset => {
+                state.serialize_field("t", &14)?;
+            }
+            Self::HostTransfer(ref public_id) => {
+                state.serialize_field("t", &15)?;
+                state.serialize_field("c", public_id)?;
+            }
+            Self::GameEnd(ref winner) => {
+                state.serialize_field("t", &16)?;
+                state.serialize_field("c", winner)?;
+            }
+            Self::RematchRequest => {
+                state.serialize_field("t", &17)?;
+            }
+            Self::Rematch => {
+                state.serialize_field("t", &18)?;
+            }
+            Self::ServerNotice(ref message) => {
+                state.serialize_field("t", &19)?;
+                state.serialize_field("c", message)?;
+            }
+            Self::PlayerInactive => {
+                state.serialize_field("t", &20)?;
+            }
+            Self::PlayerActive => {
+                state.serialize_field("t", &21)?;
+            }
+            Self::PlayerDisconnect => {
+                state.serialize_field("t", &22)?;
+            }
+            Self::PlayerReconnect => {
+                state.serialize_field("t", &23)?;
+            }
         }
         state.end()
     }
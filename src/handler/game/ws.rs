@@ -1,189 +1,497 @@
-use std::{borrow::Cow, net::IpAddr};
+use std::{borrow::Cow, future, mem, net::IpAddr, time::Duration};
 
 use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
-use futures_util::{SinkExt as _, StreamExt as _};
-use tokio::sync::mpsc;
+use futures_util::{
+    stream::{SplitSink, SplitStream},
+    SinkExt as _, StreamExt as _,
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    time::{interval_at, timeout, timeout_at, Instant},
+};
 use uuid::Uuid;
 
-use crate::util::{log_error, log_ws, WebSocketReceiveAction, WebSocketSendAction};
+use crate::{
+    config, metrics,
+    util::{
+        log_error, log_ws,
+        rate_limit::{try_acquire_ws_message, TokenBucket},
+        shutdown, WebSocketReceiveAction, WebSocketSendAction,
+    },
+};
 
 use super::{
-    session::map::{get_immutable_session, get_mutable_session},
-    structure::{AuthData, PlayerAction, WebSocketMessaging},
-    QUEUE_MESSAGE_LIMIT,
+    identity::{set_identity_name, validate_name},
+    session::{
+        actor::{ActionOutcome, RoomHandle},
+        map::get_room,
+        GameSession,
+    },
+    structure::{AuthData, PlayerAction, RoomEventWithId, WebSocketMessaging},
 };
 
-// TODO: このコードには不備があります。
+// 閉じる合図を送ってから、クライアントの応答を待つ時間
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(3);
+// 最初のpongより先に届いたメッセージを溜めておける数
+const HANDSHAKE_MESSAGE_LIMIT: usize = 8;
 
-#[inline(always)]
-pub async fn handle_socket(mut socket: WebSocket, ip: IpAddr, room_id: Uuid) {
-    match socket.send(Message::Ping(vec![1, 2, 3])).await {
-        Ok(_) => {
-            log_ws(ip, Ok(WebSocketSendAction::SendPing));
-        }
-        Err(_) => {
-            log_ws(ip, Err(WebSocketSendAction::SendPing));
-            return;
-        }
+// 認証済みの接続が閉じる時に、部屋へ知らせる
+// タスクが中断された場合にも知らせられるように、dropで送る
+struct BoundConnection {
+    room: RoomHandle,
+    private_id: Uuid,
+    connection_id: Uuid,
+    kick_rx: oneshot::Receiver<()>,
+}
+
+impl Drop for BoundConnection {
+    fn drop(&mut self) {
+        self.room.disconnect(self.private_id, self.connection_id);
     }
-    match socket.recv().await {
-        Some(Ok(Message::Pong(_))) => {
-            log_ws(ip, WebSocketReceiveAction::GotPong);
+}
+
+/// The stages a connection goes through. Each stage has its own deadline, and the connection
+/// is dropped when nothing arrives before it.
+enum ConnectionState {
+    /// The first ping has been sent, and its pong is awaited until `player.pong_timeout`.
+    /// Messages sent before the pong are kept, and handled in order once it arrives.
+    Handshake(Vec<Message>),
+    /// Watching the room without a seat, as a spectator or before authenticating.
+    Unauthenticated,
+    /// Bound to a player's seat, so actions can be sent.
+    Authenticated(BoundConnection),
+    /// A close frame has been sent, and the client's reply is awaited until `CLOSE_TIMEOUT`.
+    /// Everything else the client sends is ignored.
+    Closing,
+}
+
+/// Waits until the room unbinds the connection. Returns `true` if it was kicked because the
+/// player connected again, or `false` if the player lost the seat.
+async fn wait_for_unbind(state: &mut ConnectionState) -> bool {
+    match state {
+        ConnectionState::Authenticated(connection) => (&mut connection.kick_rx).await.is_ok(),
+        _ => future::pending().await,
+    }
+}
+
+/// Returns the close frame to send for `msg`, or `None` if it is sent as text.
+fn get_close_frame(msg: &WebSocketMessaging) -> Option<CloseFrame<'static>> {
+    let (code, reason) = match *msg {
+        WebSocketMessaging::HeartbeatAck
+        | WebSocketMessaging::NotAccepted(_)
+        | WebSocketMessaging::SessionExpired => return None,
+        WebSocketMessaging::GotBinary => (close_code::UNSUPPORTED, "Binary Not Recognized"),
+        WebSocketMessaging::GotInvalidData => (close_code::INVALID, "Invalid Data"),
+        WebSocketMessaging::TooManyMessages => (close_code::POLICY, "Too Many Messages"),
+        WebSocketMessaging::ConnectedElsewhere => (close_code::NORMAL, "Connected Elsewhere"),
+    };
+    Some(CloseFrame {
+        code,
+        reason: Cow::from(reason),
+    })
+}
+
+async fn send_text(sender: &mut SplitSink<WebSocket, Message>, ip: IpAddr, text: String) -> bool {
+    if sender.send(Message::Text(text.clone())).await.is_err() {
+        log_ws(ip, Err(WebSocketSendAction::SendText(&text)));
+        return false;
+    }
+    log_ws(ip, Ok(WebSocketSendAction::SendText(&text)));
+    true
+}
+
+async fn send_close(
+    sender: &mut SplitSink<WebSocket, Message>,
+    ip: IpAddr,
+    cf: CloseFrame<'static>,
+) {
+    if sender.send(Message::Close(Some(cf.clone()))).await.is_err() {
+        log_ws(ip, Err(WebSocketSendAction::SendClose(&cf)));
+    } else {
+        log_ws(ip, Ok(WebSocketSendAction::SendClose(&cf)));
+    }
+}
+
+/// Forwards the room events and the replies from `ConnectionReceiver` to the client, and
+/// pings it periodically. Returns after sending a close frame or failing to send.
+async fn run_sender(
+    mut sender: SplitSink<WebSocket, Message>,
+    ip: IpAddr,
+    mut queue_rx: broadcast::Receiver<RoomEventWithId>,
+    mut conn_rx: mpsc::Receiver<WebSocketMessaging>,
+) {
+    let mut shutdown_rx = shutdown::subscribe();
+    let ping_period = Duration::from_secs(config::get().player.ping_interval);
+    let mut ping_interval = interval_at(Instant::now() + ping_period, ping_period);
+    loop {
+        tokio::select! {
+            _ = ping_interval.tick() => {
+                if sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    log_ws(ip, Err(WebSocketSendAction::SendPing));
+                    break;
+                }
+                log_ws(ip, Ok(WebSocketSendAction::SendPing));
+            }
+            val = queue_rx.recv() => match val {
+                Ok(event) => {
+                    let event_str = serde_json::to_string(&event).unwrap();
+                    if !send_text(&mut sender, ip, event_str).await {
+                        break;
+                    }
+                }
+                // 部屋が閉じられ、GameSessionと共に送信側がdropした
+                Err(broadcast::error::RecvError::Closed) => {
+                    let cf = CloseFrame {
+                        code: close_code::NORMAL,
+                        reason: Cow::from("Room Closed"),
+                    };
+                    send_close(&mut sender, ip, cf).await;
+                    break;
+                }
+                Err(error) => {
+                    log_error!("socket_recv", error);
+                    metrics::BROADCAST_LAGGED.inc();
+                    let cf = CloseFrame {
+                        code: close_code::AGAIN,
+                        reason: Cow::from("Server Lagged"),
+                    };
+                    send_close(&mut sender, ip, cf).await;
+                    break;
+                }
+            },
+            val = conn_rx.recv() => {
+                // 受信側のタスクが先に終わった
+                let Some(msg) = val else {
+                    break;
+                };
+                if let Some(cf) = get_close_frame(&msg) {
+                    send_close(&mut sender, ip, cf).await;
+                    break;
+                }
+                let text = serde_json::to_string(&msg).unwrap();
+                if !send_text(&mut sender, ip, text).await {
+                    break;
+                }
+            }
+            _ = shutdown::wait(&mut shutdown_rx) => {
+                // クライアントには再接続を促す
+                let cf = CloseFrame {
+                    code: close_code::RESTART,
+                    reason: Cow::from("Server Restarting"),
+                };
+                send_close(&mut sender, ip, cf).await;
+                break;
+            }
         }
-        _ => return,
     }
-    // vvv 通信関連の変数定義ここから vvv
-    let mut queue_rx = get_immutable_session(room_id)
-        .get_queue_sender()
-        .subscribe();
-    let (conn_tx, mut conn_rx) = mpsc::channel(QUEUE_MESSAGE_LIMIT);
-    let (mut sender, mut receiver) = socket.split();
-    // ^^^ 通信関連の変数定義ここまで ^^^
-    let mut send_task = tokio::spawn(async move {
+}
+
+/// Reads what the client sends, and moves the connection through `ConnectionState`.
+/// Replies go through `conn_tx`, so that only `run_sender` writes to the socket.
+struct ConnectionReceiver {
+    ip: IpAddr,
+    room: RoomHandle,
+    conn_tx: mpsc::Sender<WebSocketMessaging>,
+    token_bucket: TokenBucket,
+    state: ConnectionState,
+    deadline: Instant,
+    receive_timeout: Duration,
+}
+
+impl ConnectionReceiver {
+    async fn run(mut self, mut receiver: SplitStream<WebSocket>) {
         loop {
+            let is_closing = matches!(self.state, ConnectionState::Closing);
             tokio::select! {
-                val = queue_rx.recv() => match val {
-                    Ok(event) => {
-                        let event_str = serde_json::to_string(&event).unwrap();
-                        match sender.send(Message::Text(event_str.clone())).await {
-                            Ok(_) => {
-                                log_ws(ip, Ok(WebSocketSendAction::SendText(&event_str)));
-                            }
-                            Err(error) => {
-                                log_error!("socket_send", error);
-                                break;
-                            }
-                        }
-                    }
-                    Err(error) => {
-                        log_error!("socket_recv", error);
-                        let _ = sender
-                            .send(Message::Close(Some(CloseFrame {
-                                code: close_code::AGAIN,
-                                reason: Cow::from("Server Lagged"),
-                            })))
-                            .await;
-                        break;
-                    }
-                },
-                val = conn_rx.recv() => match val.unwrap() {
-                    WebSocketMessaging::HeartbeatAck
-                    | WebSocketMessaging::NotAccepted(_)
-                    | WebSocketMessaging::SessionExpired => {
-                        let text = serde_json::to_string(val.as_ref().unwrap()).unwrap();
-                        if sender.send(Message::Text(text.clone())).await.is_err() {
-                            log_ws(ip, Err(WebSocketSendAction::SendText(&text)));
+                received = timeout_at(self.deadline, receiver.next()) => match received {
+                    Ok(Some(Ok(msg))) => {
+                        if !self.handle_message(msg).await {
                             break;
                         }
-                        log_ws(ip, Ok(WebSocketSendAction::SendText(&text)));
                     }
-                    WebSocketMessaging::GotBinary => {
-                        let cf = CloseFrame {
-                            code: close_code::UNSUPPORTED,
-                            reason: Cow::from("Binary Not Recognized"),
-                        };
-                        if sender.send(Message::Close(Some(cf.clone()))).await.is_err() {
-                            log_ws(ip, Err(WebSocketSendAction::SendClose(&cf)));
-                        } else {
-                            log_ws(ip, Ok(WebSocketSendAction::SendClose(&cf)));
-                        }
-                        break;
-                    }
-                    WebSocketMessaging::GotInvalidData => {
-                        let cf = CloseFrame {
-                            code: close_code::INVALID,
-                            reason: Cow::from("Invalid Data"),
-                        };
-                        if sender.send(Message::Close(Some(cf.clone()))).await.is_err() {
-                            log_ws(ip, Err(WebSocketSendAction::SendClose(&cf)));
-                        } else {
-                            log_ws(ip, Ok(WebSocketSendAction::SendClose(&cf)));
-                        }
+                    // 切断されたか、読めないフレームが届いた
+                    Ok(_) => break,
+                    Err(_) => {
+                        log_ws(self.ip, WebSocketReceiveAction::TimedOut);
                         break;
                     }
                 },
+                is_kicked = wait_for_unbind(&mut self.state) => {
+                    self.state = ConnectionState::Unauthenticated;
+                    if is_kicked {
+                        self.reply(WebSocketMessaging::ConnectedElsewhere).await;
+                    } else {
+                        self.reply(WebSocketMessaging::SessionExpired).await;
+                    }
+                }
+                // 送信側が閉じる合図を送り終えたか、送れなくなった
+                _ = self.conn_tx.closed(), if !is_closing => self.enter_closing(),
+            }
+        }
+    }
+
+    fn enter_closing(&mut self) {
+        // 席と結び付いていれば、ここでdropして部屋に知らせる
+        self.state = ConnectionState::Closing;
+        self.deadline = Instant::now() + CLOSE_TIMEOUT;
+    }
+
+    // 閉じる合図になるメッセージを送ったら、それ以降の入力は扱わない
+    async fn reply(&mut self, msg: WebSocketMessaging) {
+        let is_close = get_close_frame(&msg).is_some();
+        if self.conn_tx.send(msg).await.is_err() || is_close {
+            self.enter_closing();
+        }
+    }
+
+    /// Returns `false` if the connection should be dropped now.
+    async fn handle_message(&mut self, msg: Message) -> bool {
+        if let Message::Close(ref c) = msg {
+            log_ws(self.ip, WebSocketReceiveAction::GotClose(c));
+            return false;
+        }
+        match self.state {
+            ConnectionState::Closing => return true,
+            // 最初のpongが届くまでは、期限を延ばさない
+            ConnectionState::Handshake(_) => {}
+            ConnectionState::Unauthenticated | ConnectionState::Authenticated(_) => {
+                self.deadline = Instant::now() + self.receive_timeout;
             }
         }
-    });
-    let mut recv_task = tokio::spawn(async move {
-        let mut private_id = None::<Uuid>;
-        while let Some(Ok(msg)) = receiver.next().await {
-            let permit = conn_tx.reserve().await.unwrap();
-            match msg {
-                Message::Text(text) => {
-                    log_ws(ip, WebSocketReceiveAction::GotText(&text));
-                    match private_id {
-                        Some(private_id)
-                            if get_immutable_session(room_id).contains_player(private_id) =>
-                        {
-                            match serde_json::from_str::<PlayerAction>(&text) {
-                                Ok(action) => {
-                                    if let Some(msg) = handle_game(action, room_id, private_id) {
-                                        permit.send(msg);
-                                    }
-                                }
-                                _ => permit.send(WebSocketMessaging::GotInvalidData),
+        match msg {
+            Message::Pong(_) => {
+                log_ws(self.ip, WebSocketReceiveAction::GotPong);
+                match self.state {
+                    ConnectionState::Handshake(ref mut pending) => {
+                        let pending = mem::take(pending);
+                        self.state = ConnectionState::Unauthenticated;
+                        self.deadline = Instant::now() + self.receive_timeout;
+                        for msg in pending {
+                            if let ConnectionState::Closing = self.state {
+                                break;
                             }
+                            self.handle_data(msg).await;
                         }
-                        Some(_) => {
-                            private_id = None;
-                            permit.send(WebSocketMessaging::SessionExpired);
-                        }
-                        None => match serde_json::from_str::<AuthData>(&text) {
-                            Ok(AuthData {
-                                private_id: provided_private_id,
-                            }) if get_immutable_session(room_id)
-                                .contains_player(provided_private_id) =>
-                            {
-                                private_id = Some(provided_private_id);
-                            }
-                            _ => permit.send(WebSocketMessaging::GotInvalidData),
-                        },
                     }
+                    // pongは生きている証拠として扱うだけで、返事はしない
+                    ConnectionState::Authenticated(ref connection) => {
+                        let _ = self.room.heartbeat(connection.private_id).await;
+                    }
+                    ConnectionState::Unauthenticated | ConnectionState::Closing => {}
                 }
-                Message::Binary(_) => {
-                    log_ws(ip, WebSocketReceiveAction::GotBinary);
-                    permit.send(WebSocketMessaging::GotBinary);
+            }
+            // pingへの応答はaxumが送る
+            Message::Ping(_) => log_ws(self.ip, WebSocketReceiveAction::GotPing),
+            msg => match self.state {
+                ConnectionState::Handshake(ref mut pending) => {
+                    if pending.len() < HANDSHAKE_MESSAGE_LIMIT {
+                        pending.push(msg);
+                    } else {
+                        self.reply(WebSocketMessaging::TooManyMessages).await;
+                    }
                 }
-                Message::Close(c) => {
-                    log_ws(ip, WebSocketReceiveAction::GotClose(&c));
-                    break;
+                _ => self.handle_data(msg).await,
+            },
+        }
+        true
+    }
+
+    async fn handle_data(&mut self, msg: Message) {
+        // 行動ごとに部屋のタスクへ命令を送るので、送りすぎる接続は切る
+        if !try_acquire_ws_message(&mut self.token_bucket) {
+            self.reply(WebSocketMessaging::TooManyMessages).await;
+            return;
+        }
+        let text = match msg {
+            Message::Text(text) => text,
+            Message::Binary(_) => {
+                log_ws(self.ip, WebSocketReceiveAction::GotBinary);
+                self.reply(WebSocketMessaging::GotBinary).await;
+                return;
+            }
+            _ => {
+                self.reply(WebSocketMessaging::GotInvalidData).await;
+                return;
+            }
+        };
+        log_ws(self.ip, WebSocketReceiveAction::GotText(&text));
+        match self.state {
+            // 部屋にいるかどうかは、部屋のタスクが行動と一緒に確かめる
+            ConnectionState::Authenticated(ref connection) => {
+                let private_id = connection.private_id;
+                let Ok(action) = serde_json::from_str::<PlayerAction>(&text) else {
+                    self.reply(WebSocketMessaging::GotInvalidData).await;
+                    return;
+                };
+                match handle_game(action, &self.room, private_id).await {
+                    Some(WebSocketMessaging::SessionExpired) => {
+                        self.state = ConnectionState::Unauthenticated;
+                        self.reply(WebSocketMessaging::SessionExpired).await;
+                    }
+                    Some(msg) => self.reply(msg).await,
+                    None => {}
                 }
-                _ => permit.send(WebSocketMessaging::GotInvalidData),
             }
+            ConnectionState::Unauthenticated => {
+                let Ok(AuthData { private_id }) = serde_json::from_str::<AuthData>(&text) else {
+                    self.reply(WebSocketMessaging::GotInvalidData).await;
+                    return;
+                };
+                let (kick_tx, kick_rx) = oneshot::channel();
+                match self.room.connect(private_id, kick_tx).await {
+                    Some(Some(connection_id)) => {
+                        self.state = ConnectionState::Authenticated(BoundConnection {
+                            room: self.room.clone(),
+                            private_id,
+                            connection_id,
+                            kick_rx,
+                        });
+                    }
+                    _ => self.reply(WebSocketMessaging::GotInvalidData).await,
+                }
+            }
+            // handle_messageで振り分けているので、ここには来ない
+            ConnectionState::Handshake(_) | ConnectionState::Closing => {}
+        }
+    }
+}
+
+pub async fn handle_socket(mut socket: WebSocket, ip: IpAddr, room_id: Uuid) {
+    let player_config = &config::get().player;
+    let handshake_timeout = Duration::from_secs(player_config.pong_timeout);
+    // 最初のpingへのpongが届くまでが、Handshakeの段階になる
+    let handshake_deadline = Instant::now() + handshake_timeout;
+    match timeout(handshake_timeout, socket.send(Message::Ping(vec![1, 2, 3]))).await {
+        Ok(Ok(_)) => {
+            log_ws(ip, Ok(WebSocketSendAction::SendPing));
         }
-    });
+        _ => {
+            log_ws(ip, Err(WebSocketSendAction::SendPing));
+            return;
+        }
+    }
+    // アップグレードを待つ間に部屋が閉じられていることもある
+    let Some(room) = get_room(room_id) else {
+        return;
+    };
+    let Some(queue_rx) = room
+        .inspect(|session| session.get_queue_sender().subscribe())
+        .await
+    else {
+        return;
+    };
+    let (conn_tx, conn_rx) = mpsc::channel(config::get().room.queue_message_limit);
+    let (sender, receiver) = socket.split();
+    let connection_receiver = ConnectionReceiver {
+        ip,
+        room,
+        conn_tx,
+        token_bucket: TokenBucket::new(config::get().limit.ws_burst),
+        state: ConnectionState::Handshake(Vec::new()),
+        deadline: handshake_deadline,
+        // pingを送ってからpong_timeoutが過ぎても何も届かなければ、接続が切れたものとみなす
+        receive_timeout: Duration::from_secs(player_config.ping_interval) + handshake_timeout,
+    };
+    let mut send_task = tokio::spawn(run_sender(sender, ip, queue_rx, conn_rx));
+    let mut recv_task = tokio::spawn(connection_receiver.run(receiver));
     tokio::select! {
-        _ = (&mut send_task) => recv_task.abort(),
+        // 閉じる合図を送った後は、受信側がクライアントの応答を待ってから終わる
+        _ = (&mut send_task) => {
+            let _ = recv_task.await;
+        }
         _ = (&mut recv_task) => send_task.abort(),
     };
 }
 
-#[inline(always)]
-fn handle_game(
+async fn handle_game(
     action: PlayerAction,
-    room_id: Uuid,
+    room: &RoomHandle,
     private_id: Uuid,
 ) -> Option<WebSocketMessaging> {
-    let mut session = get_mutable_session(room_id);
-    match action {
+    let outcome = match action {
         PlayerAction::Heartbeat => {
-            session.update_heartbeat(private_id);
-            return Some(WebSocketMessaging::HeartbeatAck);
-        }
-        PlayerAction::SelectPiece(position) => {
-            if session.select_piece(private_id, position).is_err() {
-                return Some(WebSocketMessaging::NotAccepted(action));
-            }
+            return Some(match room.heartbeat(private_id).await {
+                Some(ActionOutcome::Accepted) => WebSocketMessaging::HeartbeatAck,
+                _ => WebSocketMessaging::SessionExpired,
+            });
         }
+        PlayerAction::SelectPiece(position) => room.select_piece(private_id, position).await,
         PlayerAction::MovePiece(old_position, new_position) => {
-            if session
-                .move_piece(private_id, old_position, new_position)
-                .is_err()
-            {
-                return Some(WebSocketMessaging::NotAccepted(action));
+            room.move_piece(private_id, old_position, new_position)
+                .await
+        }
+        ref action => {
+            let action = action.clone();
+            room.update(move |session| apply_action(session, private_id, action))
+                .await
+        }
+    };
+    match outcome {
+        Some(ActionOutcome::Accepted) => None,
+        Some(ActionOutcome::Rejected) => Some(WebSocketMessaging::NotAccepted(action)),
+        // 部屋から外されたか、部屋が閉じられた
+        Some(ActionOutcome::NotInRoom) | None => Some(WebSocketMessaging::SessionExpired),
+    }
+}
+
+// 部屋のタスクの中で実行される
+fn apply_action(
+    session: &mut GameSession,
+    private_id: Uuid,
+    action: PlayerAction,
+) -> ActionOutcome {
+    // プレイヤーからのメッセージは全て、生きている証拠として扱う
+    if !session.update_heartbeat(private_id) {
+        return ActionOutcome::NotInRoom;
+    }
+    let result = match action {
+        // これらは専用の命令で送られるので、ここには来ない
+        PlayerAction::Heartbeat | PlayerAction::SelectPiece(_) | PlayerAction::MovePiece(_, _) => {
+            return ActionOutcome::Rejected;
+        }
+        PlayerAction::Rename(ref name) => {
+            let Some(name) = validate_name(name) else {
+                return ActionOutcome::Rejected;
+            };
+            let result = session.rename_player(private_id, name.as_str());
+            if result.is_ok() {
+                set_identity_name(session.get_player(private_id).unwrap().token, &name);
             }
+            result
         }
+        PlayerAction::Ready(is_ready) => session.set_ready(private_id, is_ready),
+        PlayerAction::RequestRematch => session.request_rematch(private_id),
+        PlayerAction::Kick(_)
+        | PlayerAction::Lock(_)
+        | PlayerAction::ChangeSide(_, _)
+        | PlayerAction::ResetBoard
+        | PlayerAction::TransferHost(_)
+        | PlayerAction::StartGame
+            if !session.is_host(private_id) =>
+        {
+            return ActionOutcome::Rejected;
+        }
+        PlayerAction::Kick(target_public_id) => {
+            session.host_kick_player(private_id, target_public_id)
+        }
+        PlayerAction::Lock(is_locked) => {
+            session.host_set_locked(private_id, is_locked);
+            Ok(())
+        }
+        PlayerAction::ChangeSide(target_public_id, side) => {
+            session.host_change_side(private_id, target_public_id, side)
+        }
+        PlayerAction::ResetBoard => session.host_reset_board(private_id),
+        PlayerAction::TransferHost(target_public_id) => {
+            session.host_transfer(private_id, target_public_id)
+        }
+        PlayerAction::StartGame => {
+            let public_id = session.get_public_id(private_id);
+            session.start_game(public_id)
+        }
+    };
+    match result {
+        Ok(()) => ActionOutcome::Accepted,
+        Err(_) => ActionOutcome::Rejected,
     }
-    None
 }
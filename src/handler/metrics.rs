@@ -0,0 +1,47 @@
+use axum::{
+    extract::{MatchedPath, Request},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+};
+
+use crate::{handler::game::count_rooms_and_players, metrics, util::SimpleResponse};
+
+pub async fn serve_metrics() -> Response {
+    let mut content = String::new();
+    let (room_count, player_counts) = count_rooms_and_players().await;
+    metrics::write_gauge(
+        &mut content,
+        "numbers_rooms_active",
+        "Number of open rooms.",
+        [(None, room_count as i64)],
+    );
+    metrics::write_gauge(
+        &mut content,
+        "numbers_players",
+        "Number of players in rooms by side.",
+        player_counts
+            .iter()
+            .map(|(side, count)| (Some(("side", side.as_str())), *count as i64)),
+    );
+    metrics::render(&mut content);
+    SimpleResponse {
+        status_code: StatusCode::OK,
+        content_type: "text/plain; version=0.0.4; charset=utf-8",
+        content,
+    }
+    .into_response()
+}
+
+pub async fn count_http_middleware(
+    matched_path: Option<MatchedPath>,
+    req: Request,
+    next: Next,
+) -> Response {
+    // 部屋のIDごとに数えると際限なく増えてしまうので、ルーティングに使われたパターンで数える
+    let route =
+        matched_path.map_or_else(|| "unmatched".to_owned(), |path| path.as_str().to_owned());
+    let res = next.run(req).await;
+    metrics::HTTP_REQUESTS.inc(&[&route, res.status().as_str()]);
+    res
+}
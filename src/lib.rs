@@ -0,0 +1,225 @@
+//! The server of the game, as a library so that the tests can drive the same router in-process.
+
+use std::{error::Error, future, io, net::SocketAddr, time::Duration};
+
+use axum::{
+    middleware,
+    routing::{delete, get, post},
+    Router,
+};
+use hyper::{body::Incoming, Request};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server,
+};
+use tower::{util::ServiceExt as _, Service as _};
+
+use self::util::{log_error, log_event, shutdown, unwrap_infallible};
+
+pub mod config;
+mod handler;
+mod metrics;
+mod util;
+
+/// Builds the router that serves every page and API of the server.
+pub fn app() -> Router {
+    Router::new()
+        .route("/", get(handler::file::serve_index_html))
+        .route("/metrics", get(handler::metrics::serve_metrics))
+        .route("/rooms", get(handler::game::lobby::rooms))
+        .route("/rooms/ws", get(handler::game::lobby::serve_lobby_ws))
+        .route(
+            "/players/:public_id",
+            get(handler::game::rating::player_profile),
+        )
+        .route("/leaderboard", get(handler::game::rating::leaderboard))
+        .route(
+            "/games/import",
+            post(handler::game::notation::import_game).layer(middleware::from_fn(
+                util::rate_limit::rate_limit_import_middleware,
+            )),
+        )
+        .nest(
+            "/games/:game_id",
+            Router::new()
+                .route("/replay", get(handler::game::replay::replay))
+                .route("/replay/ws", get(handler::game::replay::serve_replay_ws))
+                .route("/position", get(handler::game::replay::position))
+                .route("/export", get(handler::game::notation::export_game)),
+        )
+        .nest(
+            "/matchmaking",
+            Router::new()
+                .route("/join", post(handler::game::matchmaking::join))
+                .route(
+                    "/:ticket_id",
+                    get(handler::game::matchmaking::poll)
+                        .delete(handler::game::matchmaking::cancel),
+                ),
+        )
+        .nest(
+            "/admin",
+            Router::new()
+                .route("/rooms", get(handler::game::admin::list_rooms))
+                .route(
+                    "/rooms/:room_id",
+                    get(handler::game::admin::room_detail).delete(handler::game::admin::close_room),
+                )
+                .route(
+                    "/rooms/:room_id/players/:public_id",
+                    delete(handler::game::admin::kick_player),
+                )
+                .route("/notice", post(handler::game::admin::broadcast_notice))
+                .layer(middleware::from_fn(handler::game::admin::admin_auth)),
+        )
+        .nest(
+            "/room",
+            Router::new()
+                .route("/new", get(handler::game::http::new_room))
+                .route("/main.js", get(handler::file::serve_game_js))
+                .nest(
+                    "/:room_id",
+                    Router::new()
+                        .route("/", get(handler::file::serve_game_html))
+                        .route("/room_data", get(handler::game::http::room_data))
+                        .route("/ws", get(handler::game::http::serve_ws))
+                        .route("/export", get(handler::game::notation::export_room))
+                        .route("/players", post(handler::game::http::join))
+                        .route("/players/me", delete(handler::game::http::leave))
+                        .layer(middleware::from_fn(
+                            handler::game::http::room_existence_check,
+                        )),
+                ),
+        )
+        .layer(middleware::from_fn(
+            util::rate_limit::rate_limit_http_middleware,
+        ))
+        .layer(middleware::from_fn(handler::metrics::count_http_middleware))
+        .layer(middleware::from_fn(util::log_http_middleware))
+}
+
+/// Starts the tasks that run alongside the router. Call once before serving.
+pub fn spawn_background_tasks() {
+    handler::game::matchmaking::spawn_matchmaker();
+    handler::game::lobby::spawn_lobby_publisher();
+    handler::game::identity::spawn_identity_sweeper();
+}
+
+/// Serves until a shutdown signal arrives, then saves the state if configured.
+/// Call `config::init` before this.
+pub fn run() -> Result<(), Box<dyn Error>> {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(async {
+        #[cfg(windows)]
+        {
+            util::windows_setup();
+        }
+        #[cfg(debug_assertions)]
+        {
+            log_event!(Info, "This is debug build.");
+        }
+        #[cfg(not(debug_assertions))]
+        {
+            log_event!(Info, "This is release build.");
+        }
+        let server_config = &config::get().server;
+        if let Some(ref state_file) = server_config.state_file {
+            match handler::game::persistence::load_state(state_file) {
+                Ok(0) => {}
+                Ok(room_count) => {
+                    log_event!(
+                        Info,
+                        "Restored {} rooms from {}",
+                        room_count,
+                        state_file.display()
+                    );
+                }
+                Err(error) => {
+                    log_error!("load_state", error);
+                }
+            }
+        }
+        spawn_background_tasks();
+        let addr = SocketAddr::new(server_config.bind_address, server_config.port);
+        let mut make_service = app().into_make_service_with_connect_info::<SocketAddr>();
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        log_event!(Info, "Serving at http://{}/", listener.local_addr()?);
+        let shutdown_signal = async {
+            if let Err(error) = shutdown::wait_for_signal().await {
+                // シグナルを受け取れない場合でも、サーバー自体は動かし続ける
+                log_error!("signal", error);
+                future::pending::<()>().await;
+            }
+        };
+        tokio::pin!(shutdown_signal);
+        loop {
+            let (socket, remote_addr) = tokio::select! {
+                result = listener.accept() => match result {
+                    Ok(accepted) => accepted,
+                    Err(error) => {
+                        // EMFILEなどは時間が経てば解消されることが多いので、少し待ってから再試行する
+                        log_error!("accept", error);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                },
+                _ = &mut shutdown_signal => break,
+            };
+            let tower_service = unwrap_infallible(make_service.call(remote_addr).await);
+            let task_guard = shutdown::track_task();
+            tokio::spawn(async move {
+                let _task_guard = task_guard;
+                let mut shutdown_rx = shutdown::subscribe();
+                let socket = TokioIo::new(socket);
+                let hyper_service =
+                    hyper::service::service_fn(move |request: Request<Incoming>| {
+                        tower_service.clone().oneshot(request)
+                    });
+                let mut hyper_server = server::conn::auto::Builder::new(TokioExecutor::new());
+                hyper_server.http1().title_case_headers(true);
+                let connection = hyper_server.serve_connection_with_upgrades(socket, hyper_service);
+                tokio::pin!(connection);
+                let result = tokio::select! {
+                    result = connection.as_mut() => result,
+                    _ = shutdown::wait(&mut shutdown_rx) => {
+                        // 処理中のリクエストには応答してから閉じる
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                };
+                if let Err(error) = result {
+                    if error
+                        .downcast_ref::<io::Error>()
+                        .is_none_or(|e| e.kind() != io::ErrorKind::UnexpectedEof)
+                    {
+                        log_error!("serve", error);
+                    }
+                }
+            });
+        }
+        drop(listener);
+        log_event!(Info, "Shutting down...");
+        shutdown::trigger();
+        let shutdown_timeout = Duration::from_secs(server_config.shutdown_timeout);
+        if tokio::time::timeout(shutdown_timeout, shutdown::wait_for_drain())
+            .await
+            .is_err()
+        {
+            log_event!(
+                Warn,
+                "Some connections did not close in time and were dropped."
+            );
+        }
+        if let Some(ref state_file) = server_config.state_file {
+            match handler::game::persistence::save_state(state_file).await {
+                Ok(()) => log_event!(Info, "Saved rooms to {}", state_file.display()),
+                Err(error) => {
+                    log_error!("save_state", error);
+                }
+            }
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,190 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::atomic::{AtomicI64, AtomicU64, Ordering},
+};
+
+use parking_lot::Mutex;
+
+pub static WEBSOCKET_CONNECTIONS: Gauge = Gauge::new(
+    "numbers_websocket_connections",
+    "Number of open WebSocket connections.",
+);
+pub static MOVES_ACCEPTED: Counter =
+    Counter::new("numbers_moves_accepted_total", "Number of accepted moves.");
+pub static MOVES_REJECTED: CounterVec = CounterVec::new(
+    "numbers_moves_rejected_total",
+    "Number of rejected moves by reason.",
+    &["reason"],
+);
+pub static BROADCAST_LAGGED: Counter = Counter::new(
+    "numbers_broadcast_lagged_total",
+    "Number of sockets closed because they fell behind the room events.",
+);
+pub static HTTP_REQUESTS: CounterVec = CounterVec::new(
+    "numbers_http_requests_total",
+    "Number of HTTP requests by route and status.",
+    &["route", "status"],
+);
+pub static RATE_LIMITED: CounterVec = CounterVec::new(
+    "numbers_rate_limited_total",
+    "Number of HTTP requests and WebSocket messages rejected by rate limits.",
+    &["kind"],
+);
+pub static GAMES_FINISHED: CounterVec = CounterVec::new(
+    "numbers_games_finished_total",
+    "Number of finished games by winning side.",
+    &["winner"],
+);
+
+pub struct Counter {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicU64,
+}
+
+impl Counter {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicU64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "counter");
+        let _ = writeln!(out, "{} {}", self.name, self.value.load(Ordering::Relaxed));
+    }
+}
+
+pub struct Gauge {
+    name: &'static str,
+    help: &'static str,
+    value: AtomicI64,
+}
+
+impl Gauge {
+    pub const fn new(name: &'static str, help: &'static str) -> Self {
+        Self {
+            name,
+            help,
+            value: AtomicI64::new(0),
+        }
+    }
+
+    pub fn inc(&self) {
+        self.value.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.value.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn write(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "gauge");
+        let _ = writeln!(out, "{} {}", self.name, self.value.load(Ordering::Relaxed));
+    }
+}
+
+/// A counter split by label values. Label values must have a small, fixed set of values,
+/// so never use IDs or anything chosen by clients.
+pub struct CounterVec {
+    name: &'static str,
+    help: &'static str,
+    label_names: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        label_names: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            label_names,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// `label_values` must be in the same order as `label_names`.
+    pub fn inc(&self, label_values: &[&str]) {
+        debug_assert_eq!(label_values.len(), self.label_names.len());
+        let key = label_values.iter().map(|&value| value.to_owned()).collect();
+        *self.values.lock().entry(key).or_insert(0) += 1;
+    }
+
+    fn write(&self, out: &mut String) {
+        write_header(out, self.name, self.help, "counter");
+        for (label_values, value) in self.values.lock().iter() {
+            let labels = self
+                .label_names
+                .iter()
+                .zip(label_values)
+                .map(|(name, value)| (*name, value.as_str()));
+            write_sample(out, self.name, labels, *value);
+        }
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, metric_type: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+fn write_sample<'a, I, V>(out: &mut String, name: &str, labels: I, value: V)
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+    V: std::fmt::Display,
+{
+    out.push_str(name);
+    let mut is_first = true;
+    for (label_name, label_value) in labels {
+        out.push(if is_first { '{' } else { ',' });
+        is_first = false;
+        let _ = write!(out, "{}=\"", label_name);
+        for c in label_value.chars() {
+            match c {
+                '\\' => out.push_str("\\\\"),
+                '"' => out.push_str("\\\""),
+                '\n' => out.push_str("\\n"),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+    if !is_first {
+        out.push('}');
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+/// Writes a gauge whose values are computed at scrape time rather than kept in a static.
+/// Each sample has at most one label.
+pub fn write_gauge<'a, I>(out: &mut String, name: &str, help: &str, samples: I)
+where
+    I: IntoIterator<Item = (Option<(&'a str, &'a str)>, i64)>,
+{
+    write_header(out, name, help, "gauge");
+    for (label, value) in samples {
+        write_sample(out, name, label, value);
+    }
+}
+
+/// Renders every static metric in the Prometheus text exposition format.
+pub fn render(out: &mut String) {
+    WEBSOCKET_CONNECTIONS.write(out);
+    MOVES_ACCEPTED.write(out);
+    MOVES_REJECTED.write(out);
+    BROADCAST_LAGGED.write(out);
+    HTTP_REQUESTS.write(out);
+    GAMES_FINISHED.write(out);
+    RATE_LIMITED.write(out);
+}
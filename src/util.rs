@@ -14,23 +14,43 @@ use axum::{
 };
 use chrono::{
     format::{DelayedFormat, StrftimeItems},
-    Local,
+    Local, Utc,
 };
 use rand::seq::{IteratorRandom as _, SliceRandom as _};
+use serde_json::{Map, Value};
 
-use self::direction_arrow::DirectionArrow;
+use self::{
+    direction_arrow::DirectionArrow,
+    logger::{Event, Level},
+};
 
 mod direction_arrow;
 
 pub mod deser_utils;
+pub mod logger;
+pub mod rate_limit;
+pub mod shutdown;
 
 macro_rules! log_error {
     ($identifier:literal, $error:expr) => {
-        eprintln!(concat!("Error(", $identifier, "): {:#}"), $error);
+        $crate::util::logger::log_error(module_path!(), $identifier, &$error)
+    };
+}
+
+/// Logs a message at the given level, e.g. `log_event!(Info, "Serving at {}", addr)`.
+/// The target is the module path of the caller.
+macro_rules! log_event {
+    ($level:ident, $($arg:tt)+) => {
+        $crate::util::logger::log(
+            $crate::util::logger::Level::$level,
+            $crate::util::logger::module_path_to_target(module_path!()),
+            format_args!($($arg)+),
+        )
     };
 }
 
 pub(crate) use log_error;
+pub(crate) use log_event;
 
 /// This function unwraps the Result which is Infallible,
 /// indicating unwrapping this Result will never fail.
@@ -47,6 +67,12 @@ pub fn get_local_time() -> DelayedFormat<StrftimeItems<'static>> {
     Local::now().format("%F %T")
 }
 
+/// Seconds since the UNIX epoch, for timestamps that are saved or sent to clients.
+#[inline(always)]
+pub fn get_unix_time() -> u64 {
+    Utc::now().timestamp().max(0) as u64
+}
+
 #[inline(always)]
 pub fn generate_name() -> String {
     let mut thread_rng = rand::thread_rng();
@@ -109,44 +135,55 @@ pub async fn log_http_middleware(
     req: Request,
     next: Next,
 ) -> Response {
-    let time = get_local_time();
-    let ip = addr.ip().to_string();
     let url = match uri.query() {
         Some(query) => format!("{}?{}", uri.path(), query),
         None => uri.path().to_owned(),
     };
     let res = next.run(req).await;
     let status = res.status();
-    print!("[HTTP] ");
-    if status.as_u16() >= 400 {
-        print!("\x1b[31m");
-    } else if status.as_u16() >= 300 {
-        print!("\x1b[34m");
-    }
-    print!(
-        "[{}] {} {} {} {} ({})",
-        time,
-        ip,
-        DirectionArrow::from(status),
-        method,
-        url,
-        status.as_str()
-    );
-    if status.as_u16() >= 300 {
-        print!("\x1b[0m");
+    let level = if status.as_u16() >= 400 {
+        Level::Warn
+    } else {
+        Level::Info
+    };
+    if logger::enabled(level, "http") {
+        let ip = addr.ip();
+        let mut fields = Map::new();
+        fields.insert("ip".to_owned(), Value::from(ip.to_string()));
+        fields.insert("method".to_owned(), Value::from(method.as_str()));
+        fields.insert("url".to_owned(), Value::from(url.as_str()));
+        fields.insert("status".to_owned(), Value::from(status.as_u16()));
+        logger::emit(Event {
+            level,
+            target: "http",
+            message: format_args!(
+                "{} {} {} {} ({})",
+                ip,
+                DirectionArrow::from(status),
+                method,
+                url,
+                status.as_str()
+            ),
+            fields,
+            highlight: status.is_redirection(),
+        });
     }
-    println!();
     res
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy)]
 pub enum WebSocketReceiveAction<'a> {
+    GotPing,
     GotPong,
     GotText(&'a str),
     GotBinary,
     GotClose(&'a Option<CloseFrame<'static>>),
+    // pingへのpongも含めて、決められた時間内に何も届かなかった
+    TimedOut,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, Copy)]
 pub enum WebSocketSendAction<'a> {
     SendPing,
@@ -180,50 +217,92 @@ where
     T: Into<WebSocketAction<'a>>,
 {
     let action = action.into();
-    let time = get_local_time();
-    print!("[ WS ] ");
-    if matches!(action, WebSocketAction::Send(Err(_))) {
-        print!("\x1b[31m");
+    let level = match action {
+        WebSocketAction::Connect
+        | WebSocketAction::Disconnect
+        | WebSocketAction::Receive(WebSocketReceiveAction::GotClose(_))
+        | WebSocketAction::Receive(WebSocketReceiveAction::TimedOut)
+        | WebSocketAction::Send(Ok(WebSocketSendAction::SendClose(_))) => Level::Info,
+        WebSocketAction::Receive(_) | WebSocketAction::Send(Ok(_)) => Level::Debug,
+        WebSocketAction::Send(Err(_)) => Level::Warn,
+    };
+    if !logger::enabled(level, "ws") {
+        return;
     }
-    print!("[{}] {} {} ", time, ip, DirectionArrow::from(action));
-    match action {
-        WebSocketAction::Connect => print!("Connected"),
-        WebSocketAction::Disconnect => print!("Disconnected"),
+    let mut fields = Map::new();
+    fields.insert("ip".to_owned(), Value::from(ip.to_string()));
+    let (event, description) = match action {
+        WebSocketAction::Connect => ("connect", "Connected".to_owned()),
+        WebSocketAction::Disconnect => ("disconnect", "Disconnected".to_owned()),
         WebSocketAction::Receive(inner_action) => match inner_action {
-            WebSocketReceiveAction::GotPong => print!("Sent ping"),
-            WebSocketReceiveAction::GotText(text) => print!("Sent text data: {}", text),
-            WebSocketReceiveAction::GotBinary => print!("Sent binary data: (Cannot process)"),
-            WebSocketReceiveAction::GotClose(c) => {
-                if let Some(cf) = c {
-                    println!("Sent close: {} \"{}\"", cf.code, cf.reason);
-                } else {
-                    println!("Sent close without close frame");
-                }
-            }
-        },
-        WebSocketAction::Send(inner_action) => match inner_action {
-            Ok(WebSocketSendAction::SendPing) => print!("Sent ping"),
-            Ok(WebSocketSendAction::SendText(text)) => print!("Sent text data: {}", text),
-            Ok(WebSocketSendAction::SendBinary) => print!("Sent binary data: (Cannot process)"),
-            Ok(WebSocketSendAction::SendClose(cf)) => {
-                print!("Sent close: {} \"{}\"", cf.code, cf.reason)
-            }
-            Err(WebSocketSendAction::SendPing) => print!("Could not send ping"),
-            Err(WebSocketSendAction::SendText(text)) => {
-                print!("Could not send text data: {}", text)
+            WebSocketReceiveAction::GotPing => ("receive_ping", "Sent ping".to_owned()),
+            WebSocketReceiveAction::GotPong => ("receive_pong", "Sent ping".to_owned()),
+            WebSocketReceiveAction::GotText(text) => {
+                // クライアントは認証のためにprivate_idを"i"として送ってくるので、そのままログに残してはいけない
+                // (サーバーから送る"i"はpublic_idなので、受信したデータに限る)
+                let text = logger::redact_text(text, &["private_id", "i"]);
+                fields.insert("data".to_owned(), Value::from(text.as_str()));
+                ("receive_text", format!("Sent text data: {}", text))
             }
-            Err(WebSocketSendAction::SendBinary) => {
-                print!("Could not send binary data: (Cannot process)")
-            }
-            Err(WebSocketSendAction::SendClose(cf)) => {
-                print!("Could not send close: {} \"{}\"", cf.code, cf.reason)
+            WebSocketReceiveAction::GotBinary => (
+                "receive_binary",
+                "Sent binary data: (Cannot process)".to_owned(),
+            ),
+            WebSocketReceiveAction::GotClose(c) => (
+                "receive_close",
+                match c {
+                    Some(cf) => {
+                        insert_close_frame(&mut fields, cf);
+                        format!("Sent close: {} \"{}\"", cf.code, cf.reason)
+                    }
+                    None => "Sent close without close frame".to_owned(),
+                },
+            ),
+            WebSocketReceiveAction::TimedOut => {
+                ("receive_timeout", "Sent nothing in time".to_owned())
             }
         },
-    }
-    if matches!(action, WebSocketAction::Send(Err(_))) {
-        print!("\x1b[0m");
-    }
-    println!();
+        WebSocketAction::Send(inner_action) => {
+            fields.insert("ok".to_owned(), Value::from(inner_action.is_ok()));
+            let prefix = if inner_action.is_ok() {
+                "Sent"
+            } else {
+                "Could not send"
+            };
+            match inner_action.unwrap_or_else(|inner_action| inner_action) {
+                WebSocketSendAction::SendPing => ("send_ping", format!("{} ping", prefix)),
+                WebSocketSendAction::SendText(text) => {
+                    let text = logger::redact_text(text, logger::SENSITIVE_KEYS);
+                    fields.insert("data".to_owned(), Value::from(text.as_str()));
+                    ("send_text", format!("{} text data: {}", prefix, text))
+                }
+                WebSocketSendAction::SendBinary => (
+                    "send_binary",
+                    format!("{} binary data: (Cannot process)", prefix),
+                ),
+                WebSocketSendAction::SendClose(cf) => {
+                    insert_close_frame(&mut fields, cf);
+                    (
+                        "send_close",
+                        format!("{} close: {} \"{}\"", prefix, cf.code, cf.reason),
+                    )
+                }
+            }
+        }
+    };
+    fields.insert("event".to_owned(), Value::from(event));
+    logger::emit(Event {
+        level,
+        target: "ws",
+        message: format_args!("{} {} {}", ip, DirectionArrow::from(action), description),
+        fields,
+        highlight: false,
+    });
+}
+
+fn insert_close_frame(fields: &mut Map<String, Value>, cf: &CloseFrame<'static>) {
+    fields.insert("code".to_owned(), Value::from(cf.code));
+    fields.insert("reason".to_owned(), Value::from(cf.reason.as_ref()));
 }
 
 #[cfg(windows)]
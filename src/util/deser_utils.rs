@@ -6,8 +6,8 @@ macro_rules! dcwt {
         tag: $tag:expr,
         content: $content:expr,
         with_no_content: [$($no_content_variants:ident = $ncv_init:expr),+],
-        with_single_content: [$to_vwc:ty => $($with_single_content:ident ( $_unused:ident ) = $vwc_init:expr),+],
-        with_tuplelike_content: [$to_vwtc:ty => $($with_tuplelike_content:ident ( $($tmp_p:ident),+ ) = $vwtc_init:expr),+]
+        with_single_content: [$($to_vwc:ty => $with_single_content:ident ( $_unused:ident ) = $vwc_init:expr),+],
+        with_tuplelike_content: [$($to_vwtc:ty => $with_tuplelike_content:ident ( $($tmp_p:ident),+ ) = $vwtc_init:expr),+]
     } => {
         const _: () = {
             extern crate serde as _serde;
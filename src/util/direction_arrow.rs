@@ -4,8 +4,9 @@ use axum::http::StatusCode;
 
 use super::WebSocketAction;
 
-const DIRECTION_ARROWS: &'static str = "<->-x--v";
+const DIRECTION_ARROWS: &str = "<->-x--v";
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Copy)]
 pub enum DirectionArrow {
     RTL,
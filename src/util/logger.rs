@@ -0,0 +1,222 @@
+use std::{fmt, io::Write as _};
+
+use chrono::{SecondsFormat, Utc};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::config;
+
+use super::get_local_time;
+
+/// Keys whose values are never written to the log, wherever they appear.
+pub const SENSITIVE_KEYS: &[&str] = &["private_id"];
+const REDACTED: &str = "[REDACTED]";
+
+/// 数字が大きいほど詳細なログになります。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+
+    fn tag(self) -> &'static str {
+        match self {
+            Self::Error => "ERROR",
+            Self::Warn => "WARN",
+            Self::Info => "INFO",
+            Self::Debug => "DEBUG",
+            Self::Trace => "TRACE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// The colored, one-line-per-event output for terminals.
+    #[default]
+    Human,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+pub struct Event<'a> {
+    pub level: Level,
+    /// "http"や"ws"のような名前、またはcrate名を除いたモジュールのパス
+    pub target: &'a str,
+    pub message: fmt::Arguments<'a>,
+    /// Only written by the JSON formatter; the human formatter writes `message` alone.
+    pub fields: Map<String, Value>,
+    /// Makes the human formatter print the line in blue.
+    pub highlight: bool,
+}
+
+/// Returns whether events of `level` for `target` pass the configured filter.
+/// A module filter applies to the target itself and to every target below it,
+/// e.g. `handler::game` also covers `handler::game::ws`. The longest match wins.
+pub fn enabled(level: Level, target: &str) -> bool {
+    let log_config = &config::get().log;
+    let max_level = log_config
+        .modules
+        .iter()
+        .filter(|(module, _)| {
+            target
+                .strip_prefix(module.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+        })
+        .max_by_key(|(module, _)| module.len())
+        .map_or(log_config.level, |(_, level)| *level);
+    level <= max_level
+}
+
+/// Writes `event` regardless of the filter. Check `enabled` first.
+pub fn emit(mut event: Event) {
+    redact_object(&mut event.fields, SENSITIVE_KEYS);
+    match config::get().log.format {
+        LogFormat::Human => write_human(&event),
+        LogFormat::Json => write_json(event),
+    }
+}
+
+pub fn log(level: Level, target: &str, message: fmt::Arguments) {
+    if enabled(level, target) {
+        emit(Event {
+            level,
+            target,
+            message,
+            fields: Map::new(),
+            highlight: false,
+        });
+    }
+}
+
+/// Called through `log_error!`.
+pub fn log_error(module_path: &str, context: &str, error: &dyn fmt::Display) {
+    let target = module_path_to_target(module_path);
+    if enabled(Level::Error, target) {
+        let mut fields = Map::new();
+        fields.insert("context".to_owned(), Value::from(context));
+        emit(Event {
+            level: Level::Error,
+            target,
+            message: format_args!("{:#}", error),
+            fields,
+            highlight: false,
+        });
+    }
+}
+
+pub fn module_path_to_target(module_path: &str) -> &str {
+    module_path
+        .strip_prefix(concat!(env!("CARGO_CRATE_NAME"), "::"))
+        .unwrap_or(module_path)
+}
+
+/// Replaces the values of `keys` in `value` and everything nested in it.
+pub fn redact(value: &mut Value, keys: &[&str]) {
+    match value {
+        Value::Object(object) => redact_object(object, keys),
+        Value::Array(array) => array.iter_mut().for_each(|value| redact(value, keys)),
+        _ => {}
+    }
+}
+
+fn redact_object(object: &mut Map<String, Value>, keys: &[&str]) {
+    for (key, value) in object.iter_mut() {
+        if keys.contains(&key.as_str()) {
+            *value = Value::from(REDACTED);
+        } else {
+            redact(value, keys);
+        }
+    }
+}
+
+/// Returns `text` with the values of `keys` replaced if it is JSON.
+/// Text that is not JSON is hidden entirely, since it cannot be told apart from a secret.
+pub fn redact_text(text: &str, keys: &[&str]) -> String {
+    match serde_json::from_str::<Value>(text) {
+        Ok(mut value) => {
+            redact(&mut value, keys);
+            value.to_string()
+        }
+        Err(_) => REDACTED.to_owned(),
+    }
+}
+
+/// Returns `code` only if colored log output is enabled.
+#[inline(always)]
+pub fn ansi(code: &'static str) -> &'static str {
+    if config::get().log.color {
+        code
+    } else {
+        ""
+    }
+}
+
+fn write_human(event: &Event) {
+    // エラーは従来通り、標準エラー出力に書き出す
+    if event.level == Level::Error {
+        let context = event
+            .fields
+            .get("context")
+            .and_then(Value::as_str)
+            .unwrap_or(event.target);
+        eprintln!("Error({}): {}", context, event.message);
+        return;
+    }
+    let tag = match event.target {
+        "http" => "HTTP",
+        "ws" => " WS ",
+        _ => event.level.tag(),
+    };
+    let color = if event.level == Level::Warn {
+        ansi("\x1b[31m")
+    } else if event.highlight {
+        ansi("\x1b[34m")
+    } else {
+        ""
+    };
+    let reset = if color.is_empty() {
+        ""
+    } else {
+        ansi("\x1b[0m")
+    };
+    // 複数のスレッドから同時に書き込まれても行が混ざらないように、ロックしてから一度に書き込む
+    let _ = writeln!(
+        std::io::stdout().lock(),
+        "[{}] {}[{}] {}{}",
+        tag,
+        color,
+        get_local_time(),
+        event.message,
+        reset
+    );
+}
+
+fn write_json(event: Event) {
+    let mut object = Map::new();
+    object.insert(
+        "time".to_owned(),
+        Value::from(Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)),
+    );
+    object.insert("level".to_owned(), Value::from(event.level.as_str()));
+    object.insert("target".to_owned(), Value::from(event.target));
+    object.insert("message".to_owned(), Value::from(event.message.to_string()));
+    object.extend(event.fields);
+    let _ = writeln!(std::io::stdout().lock(), "{}", Value::Object(object));
+}
@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse as _, Response},
+};
+use parking_lot::Mutex;
+
+use crate::{config, metrics};
+
+use super::{SimpleResponse, SimpleResponseWithHeaders};
+
+// 使われなくなったIPアドレスのバケツを捨てる間隔
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+// 棋譜の読み込みは手を全て検証するうえ記録として残るので、他のリクエストより厳しく制限する
+const IMPORT_BURST: u32 = 10;
+const IMPORT_RATE: f64 = 1.0 / 60.0;
+
+/// A token bucket that holds up to `burst` tokens and regains `rate` tokens per second.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenBucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl TokenBucket {
+    /// Creates a full bucket.
+    pub fn new(burst: u32) -> Self {
+        Self {
+            tokens: burst as f64,
+            updated_at: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, burst: u32, rate: f64) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * rate).min(burst as f64);
+        self.updated_at = now;
+    }
+
+    /// Takes one token, or returns how long to wait until one is available.
+    pub fn try_acquire(&mut self, burst: u32, rate: f64) -> Result<(), Duration> {
+        self.refill(burst, rate);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - self.tokens) / rate))
+        }
+    }
+
+    fn is_full(&mut self, burst: u32, rate: f64) -> bool {
+        self.refill(burst, rate);
+        self.tokens >= burst as f64
+    }
+}
+
+#[derive(Debug)]
+struct IpRateLimiter {
+    buckets: HashMap<IpAddr, TokenBucket>,
+    pruned_at: Instant,
+}
+
+impl IpRateLimiter {
+    fn new() -> Self {
+        Self {
+            buckets: HashMap::new(),
+            pruned_at: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self, ip: IpAddr, burst: u32, rate: f64) -> Result<(), Duration> {
+        if self.pruned_at.elapsed() >= PRUNE_INTERVAL {
+            // 満タンのバケツは新しく作ったものと変わらないので、捨てても制限は緩まない
+            self.buckets
+                .retain(|_, bucket| !bucket.is_full(burst, rate));
+            self.pruned_at = Instant::now();
+        }
+        self.buckets
+            .entry(ip)
+            .or_insert_with(|| TokenBucket::new(burst))
+            .try_acquire(burst, rate)
+    }
+}
+
+fn get_ip_rate_limiter() -> &'static Mutex<IpRateLimiter> {
+    static IP_RATE_LIMITER: OnceLock<Mutex<IpRateLimiter>> = OnceLock::new();
+    IP_RATE_LIMITER.get_or_init(|| Mutex::new(IpRateLimiter::new()))
+}
+
+fn get_import_rate_limiter() -> &'static Mutex<IpRateLimiter> {
+    static IMPORT_RATE_LIMITER: OnceLock<Mutex<IpRateLimiter>> = OnceLock::new();
+    IMPORT_RATE_LIMITER.get_or_init(|| Mutex::new(IpRateLimiter::new()))
+}
+
+fn too_many_requests(wait: Duration) -> Response {
+    SimpleResponseWithHeaders {
+        original_response: SimpleResponse {
+            status_code: StatusCode::TOO_MANY_REQUESTS,
+            content_type: "application/json",
+            content: r#"{"success":false,"message":"TOO_MANY_REQUESTS"}"#,
+        },
+        headers: [(
+            header::RETRY_AFTER,
+            (wait.as_secs_f64().ceil() as u64).to_string(),
+        )],
+    }
+    .into_response()
+}
+
+/// Rejects requests from IP addresses that exceed `limit.http_burst` and `limit.http_rate`.
+pub async fn rate_limit_http_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let limit_config = &config::get().limit;
+    if limit_config.http_burst == 0 {
+        return next.run(req).await;
+    }
+    let result = get_ip_rate_limiter().lock().try_acquire(
+        addr.ip(),
+        limit_config.http_burst,
+        limit_config.http_rate,
+    );
+    match result {
+        Ok(()) => next.run(req).await,
+        Err(wait) => {
+            metrics::RATE_LIMITED.inc(&["http"]);
+            too_many_requests(wait)
+        }
+    }
+}
+
+/// Rejects game record imports from IP addresses that import more than `IMPORT_BURST` records
+/// at once, or more than one a minute after that. Disabled along with `limit.http_burst`.
+pub async fn rate_limit_import_middleware(
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    if config::get().limit.http_burst == 0 {
+        return next.run(req).await;
+    }
+    let result = get_import_rate_limiter()
+        .lock()
+        .try_acquire(addr.ip(), IMPORT_BURST, IMPORT_RATE);
+    match result {
+        Ok(()) => next.run(req).await,
+        Err(wait) => {
+            metrics::RATE_LIMITED.inc(&["import"]);
+            too_many_requests(wait)
+        }
+    }
+}
+
+/// Takes a token for a message received on a WebSocket, under the `limit.ws_*` settings.
+/// Returns `false` if the connection sends too many messages and should be closed.
+pub fn try_acquire_ws_message(bucket: &mut TokenBucket) -> bool {
+    let limit_config = &config::get().limit;
+    if limit_config.ws_burst == 0 {
+        return true;
+    }
+    if bucket
+        .try_acquire(limit_config.ws_burst, limit_config.ws_rate)
+        .is_err()
+    {
+        metrics::RATE_LIMITED.inc(&["ws"]);
+        return false;
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_full_bucket_allows_a_burst_then_waits() {
+        let mut bucket = TokenBucket::new(3);
+        for _ in 0..3 {
+            assert_eq!(bucket.try_acquire(3, 2.0), Ok(()));
+        }
+        let wait = bucket.try_acquire(3, 2.0).unwrap_err();
+        // 1秒に2つ戻るので、次の1つまでは0.5秒以内
+        assert!(wait > Duration::ZERO && wait <= Duration::from_millis(500));
+    }
+
+    #[test]
+    fn tokens_come_back_over_time_up_to_the_burst() {
+        let mut bucket = TokenBucket::new(3);
+        for _ in 0..3 {
+            bucket.try_acquire(3, 2.0).unwrap();
+        }
+        // 時間を進める代わりに、最後に補充した時刻を戻す
+        bucket.updated_at -= Duration::from_secs(1);
+        for _ in 0..2 {
+            assert_eq!(bucket.try_acquire(3, 2.0), Ok(()));
+        }
+        assert!(bucket.try_acquire(3, 2.0).is_err());
+        bucket.updated_at -= Duration::from_secs(10);
+        assert!(bucket.is_full(3, 2.0));
+        for _ in 0..3 {
+            assert_eq!(bucket.try_acquire(3, 2.0), Ok(()));
+        }
+        assert!(bucket.try_acquire(3, 2.0).is_err());
+    }
+
+    #[test]
+    fn an_empty_burst_allows_nothing() {
+        let mut bucket = TokenBucket::new(0);
+        assert!(bucket.try_acquire(0, 1.0).is_err());
+    }
+}
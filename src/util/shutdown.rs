@@ -0,0 +1,89 @@
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        OnceLock,
+    },
+};
+
+use tokio::sync::{watch, Notify};
+
+fn get_shutdown_sender() -> &'static watch::Sender<bool> {
+    static SHUTDOWN_SENDER: OnceLock<watch::Sender<bool>> = OnceLock::new();
+    SHUTDOWN_SENDER.get_or_init(|| watch::channel(false).0)
+}
+
+static ACTIVE_TASK_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+fn get_drain_notify() -> &'static Notify {
+    static DRAIN_NOTIFY: OnceLock<Notify> = OnceLock::new();
+    DRAIN_NOTIFY.get_or_init(Notify::new)
+}
+
+/// Returns a receiver whose value becomes `true` once the server starts shutting down.
+pub fn subscribe() -> watch::Receiver<bool> {
+    get_shutdown_sender().subscribe()
+}
+
+pub fn is_shutting_down() -> bool {
+    *get_shutdown_sender().borrow()
+}
+
+pub fn trigger() {
+    get_shutdown_sender().send_replace(true);
+}
+
+/// Waits until `receiver` observes the shutdown.
+pub async fn wait(receiver: &mut watch::Receiver<bool>) {
+    // 送信側はstaticなのでErrにはならない
+    let _ = receiver
+        .wait_for(|is_shutting_down| *is_shutting_down)
+        .await;
+}
+
+/// Waits for SIGINT, or SIGTERM on Unix.
+pub async fn wait_for_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = sigterm.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
+}
+
+/// シャットダウン時に終了を待つべき処理(接続など)が生きている間、保持しておくためのstructです。
+pub struct ActiveTaskGuard(());
+
+pub fn track_task() -> ActiveTaskGuard {
+    ACTIVE_TASK_COUNT.fetch_add(1, Ordering::AcqRel);
+    ActiveTaskGuard(())
+}
+
+impl Drop for ActiveTaskGuard {
+    fn drop(&mut self) {
+        if ACTIVE_TASK_COUNT.fetch_sub(1, Ordering::AcqRel) == 1 {
+            get_drain_notify().notify_waiters();
+        }
+    }
+}
+
+/// Waits until every `ActiveTaskGuard` has been dropped.
+pub async fn wait_for_drain() {
+    loop {
+        let notified = get_drain_notify().notified();
+        tokio::pin!(notified);
+        // カウントを確認する前に登録しておかないと、通知を取りこぼす
+        notified.as_mut().enable();
+        if ACTIVE_TASK_COUNT.load(Ordering::Acquire) == 0 {
+            return;
+        }
+        notified.await;
+    }
+}
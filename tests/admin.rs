@@ -0,0 +1,62 @@
+//! Checks the admin API.
+
+use hyper::{header, Method, StatusCode};
+
+use self::common::Server;
+
+mod common;
+
+const ADMIN_TOKEN: &str = "test-admin-token-0123456789";
+
+const CONFIG: &str = r#"
+[server]
+admin_token = "test-admin-token-0123456789"
+
+[limit]
+# テストは全て同じIPアドレスから同時にリクエストする
+http_burst = 0
+
+[log]
+level = "error"
+"#;
+
+#[tokio::test]
+async fn room_details_leave_out_private_ids_and_tokens() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let response = server
+        .request(
+            Method::POST,
+            &format!("/room/{}/players", room_id),
+            &[],
+            r#"{"side":"bottom"}"#,
+        )
+        .await;
+    let data = serde_json::from_str::<serde_json::Value>(response.body()).unwrap();
+    let private_id = data["private_id"].as_str().unwrap();
+    let public_id = data["public_id"].as_str().unwrap();
+    // tokenはクッキーとして渡される
+    let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    let token = cookie
+        .split(';')
+        .next()
+        .and_then(|pair| pair.split_once('='))
+        .unwrap()
+        .1;
+
+    let authorization = format!("Bearer {}", ADMIN_TOKEN);
+    let response = server
+        .request(
+            Method::GET,
+            &format!("/admin/rooms/{}", room_id),
+            &[(header::AUTHORIZATION, &authorization)],
+            "",
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK, "{}", response.body());
+    let detail = response.body();
+    assert!(detail.contains(public_id));
+    assert!(!detail.contains("private_id"));
+    assert!(!detail.contains(private_id));
+    assert!(!detail.contains(token));
+}
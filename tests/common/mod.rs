@@ -0,0 +1,218 @@
+//! Runs the server's router in-process and talks to it over a real socket.
+//!
+//! The configuration is global to the process, so each test file starts one server with its own
+//! configuration, and the tests in the file share it.
+
+// テストのファイルごとに使う関数が違う
+#![allow(dead_code)]
+
+use std::{
+    net::SocketAddr,
+    sync::{mpsc, OnceLock},
+    thread,
+    time::Duration,
+};
+
+use futures_util::{SinkExt as _, StreamExt as _};
+use http_body_util::{BodyExt as _, Full};
+use hyper::{
+    body::Bytes,
+    client::conn::http1,
+    header::{self, HeaderName},
+    Method, Request, Response, StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use numbers::config::Config;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    runtime,
+    time::timeout,
+};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{protocol::CloseFrame, Message},
+    MaybeTlsStream, WebSocketStream,
+};
+
+pub type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// サーバーからの応答を待つ上限。これを過ぎたらテストを失敗させる
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(5);
+pub const HEARTBEAT: &str = r#"{"t":99}"#;
+pub const HEARTBEAT_ACK: &str = r#"{"t":100}"#;
+pub const SESSION_EXPIRED: &str = r#"{"t":102}"#;
+
+pub struct Server {
+    addr: SocketAddr,
+}
+
+impl Server {
+    /// Returns the server of this test file, starting it with `config` on the first call.
+    /// `config` is in the format of the configuration file.
+    pub fn get(config: &str) -> &'static Server {
+        static SERVER: OnceLock<Server> = OnceLock::new();
+        SERVER.get_or_init(|| {
+            numbers::config::init(Config::parse(config).unwrap());
+            let (addr_tx, addr_rx) = mpsc::channel();
+            // テストごとのランタイムはテストが終わると止まるので、サーバーは専用のランタイムで動かし続ける
+            thread::spawn(move || {
+                let rt = runtime::Builder::new_multi_thread()
+                    .enable_all()
+                    .build()
+                    .unwrap();
+                rt.block_on(async {
+                    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+                    addr_tx.send(listener.local_addr().unwrap()).unwrap();
+                    numbers::spawn_background_tasks();
+                    let make_service =
+                        numbers::app().into_make_service_with_connect_info::<SocketAddr>();
+                    axum::serve(listener, make_service).await.unwrap();
+                });
+            });
+            Server {
+                addr: addr_rx.recv().unwrap(),
+            }
+        })
+    }
+
+    pub async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        headers: &[(HeaderName, &str)],
+        body: &str,
+    ) -> Response<String> {
+        let stream = TcpStream::connect(self.addr).await.unwrap();
+        let (mut sender, connection) = http1::handshake(TokioIo::new(stream)).await.unwrap();
+        tokio::spawn(connection);
+        let mut request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::HOST, self.addr.to_string())
+            .header(header::CONTENT_TYPE, "application/json");
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        let request = request
+            .body(Full::new(Bytes::from(body.to_owned())))
+            .unwrap();
+        let (parts, body) = sender.send_request(request).await.unwrap().into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        Response::from_parts(parts, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    /// Creates a room and returns its ID.
+    pub async fn create_room(&self) -> String {
+        let response = self.request(Method::GET, "/room/new", &[], "").await;
+        response
+            .headers()
+            .get(header::LOCATION)
+            .and_then(|location| location.to_str().ok()?.strip_prefix("/room/"))
+            .expect("no room was created")
+            .to_owned()
+    }
+
+    /// Joins the room and returns the private ID.
+    pub async fn join(&self, room_id: &str) -> String {
+        let response = self
+            .request(
+                Method::POST,
+                &format!("/room/{}/players", room_id),
+                &[],
+                r#"{"side":"bottom"}"#,
+            )
+            .await;
+        let data = serde_json::from_str::<serde_json::Value>(response.body()).unwrap();
+        data["private_id"]
+            .as_str()
+            .expect("could not join the room")
+            .to_owned()
+    }
+
+    pub async fn leave(&self, room_id: &str, private_id: &str) {
+        let response = self
+            .request(
+                Method::DELETE,
+                &format!("/room/{}/players/me", room_id),
+                &[(HeaderName::from_static("x-private-id"), private_id)],
+                "",
+            )
+            .await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", response.body());
+    }
+
+    pub async fn room_data(&self, room_id: &str) -> serde_json::Value {
+        let response = self
+            .request(
+                Method::GET,
+                &format!("/room/{}/room_data", room_id),
+                &[],
+                "",
+            )
+            .await;
+        serde_json::from_str(response.body()).unwrap()
+    }
+
+    /// Opens a WebSocket to the room without reading anything, so the first ping is not
+    /// answered yet.
+    pub async fn connect(&self, room_id: &str) -> Socket {
+        self.connect_to(&format!("/room/{}/ws", room_id)).await
+    }
+
+    /// Opens a WebSocket to any endpoint of the server, such as the lobby or a replay.
+    pub async fn connect_to(&self, path: &str) -> Socket {
+        let url = format!("ws://{}{}", self.addr, path);
+        connect_async(url).await.unwrap().0
+    }
+
+    /// Opens a WebSocket to the room and answers the first ping.
+    pub async fn connect_and_pong(&self, room_id: &str) -> Socket {
+        let mut socket = self.connect(room_id).await;
+        // pingを読むとpongが用意されるので、flushで送り出す
+        match next_message(&mut socket).await {
+            Some(Message::Ping(_)) => {}
+            other => panic!("expected the first ping, got {:?}", other),
+        }
+        socket.flush().await.unwrap();
+        socket
+    }
+}
+
+pub fn auth(private_id: &str) -> Message {
+    Message::Text(format!(r#"{{"i":"{}"}}"#, private_id))
+}
+
+/// Returns `None` if the connection has ended.
+pub async fn next_message(socket: &mut Socket) -> Option<Message> {
+    match timeout(RESPONSE_TIMEOUT, socket.next()).await {
+        Ok(Some(Ok(msg))) => Some(msg),
+        Ok(_) => None,
+        Err(_) => panic!("the server sent nothing in time"),
+    }
+}
+
+/// Skips room events until `expected` arrives.
+pub async fn expect_text(socket: &mut Socket, expected: &str) {
+    loop {
+        match next_message(socket).await {
+            Some(Message::Text(text)) if text == expected => return,
+            Some(Message::Close(frame)) => panic!("expected {}, got closed: {:?}", expected, frame),
+            None => panic!("expected {}, got disconnected", expected),
+            Some(_) => {}
+        }
+    }
+}
+
+/// Skips room events until the server closes the connection, and checks that it ends after.
+pub async fn expect_close(socket: &mut Socket) -> CloseFrame<'static> {
+    let frame = loop {
+        match next_message(socket).await {
+            Some(Message::Close(frame)) => break frame.expect("closed without a close frame"),
+            None => panic!("disconnected without a close frame"),
+            Some(_) => {}
+        }
+    };
+    // 応答の閉じる合図を受け取ったサーバーは、すぐに接続を切る
+    while next_message(socket).await.is_some() {}
+    frame
+}
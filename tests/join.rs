@@ -0,0 +1,71 @@
+//! Checks how players get their names when they join rooms.
+
+use hyper::{header, Method};
+
+use self::common::Server;
+
+mod common;
+
+const CONFIG: &str = r#"
+[limit]
+# テストは全て同じIPアドレスから同時にリクエストする
+http_burst = 0
+
+[log]
+level = "error"
+"#;
+
+/// Joins the room and returns the name the player got and the cookie holding their token.
+async fn join(
+    server: &Server,
+    room_id: &str,
+    body: &str,
+    cookie: Option<&str>,
+) -> (String, String) {
+    let headers = cookie
+        .map(|cookie| vec![(header::COOKIE, cookie)])
+        .unwrap_or_default();
+    let response = server
+        .request(
+            Method::POST,
+            &format!("/room/{}/players", room_id),
+            &headers,
+            body,
+        )
+        .await;
+    let data = serde_json::from_str::<serde_json::Value>(response.body()).unwrap();
+    let name = data["name"].as_str().expect("could not join the room");
+    let cookie = response.headers()[header::SET_COOKIE].to_str().unwrap();
+    let cookie = cookie.split(';').next().unwrap();
+    (name.to_owned(), cookie.to_owned())
+}
+
+#[tokio::test]
+async fn names_changed_for_a_room_are_not_kept() {
+    let server = Server::get(CONFIG);
+    let first_room_id = server.create_room().await;
+    let (_, cookie) = join(
+        server,
+        &first_room_id,
+        r#"{"side":"bottom","name":"alice"}"#,
+        None,
+    )
+    .await;
+
+    // 同じ名前のプレイヤーがいる部屋では、別の名前で席につく
+    let second_room_id = server.create_room().await;
+    join(
+        server,
+        &second_room_id,
+        r#"{"side":"bottom","name":"alice"}"#,
+        None,
+    )
+    .await;
+    let (name, _) = join(server, &second_room_id, r#"{"side":"top"}"#, Some(&cookie)).await;
+    assert_ne!(name, "alice");
+
+    // 他の部屋では、元の名前のまま
+    let third_room_id = server.create_room().await;
+    let (name, _) = join(server, &third_room_id, r#"{"side":"top"}"#, Some(&cookie)).await;
+    assert_eq!(name, "alice");
+}
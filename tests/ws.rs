@@ -0,0 +1,190 @@
+//! Drives the game WebSocket of the server with a WebSocket client, using the default options.
+
+use std::time::Duration;
+
+use futures_util::SinkExt as _;
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+use self::common::{
+    auth, expect_close, expect_text, next_message, Server, HEARTBEAT, HEARTBEAT_ACK,
+    SESSION_EXPIRED,
+};
+
+mod common;
+
+const CONFIG: &str = r#"
+[limit]
+# テストは全て同じIPアドレスから同時にリクエストする
+http_burst = 0
+
+[log]
+level = "error"
+"#;
+
+#[tokio::test]
+async fn messages_sent_before_the_first_pong_are_handled_after_it() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let private_id = server.join(&room_id).await;
+    let mut socket = server.connect(&room_id).await;
+    // ブラウザは接続が開いた直後に認証するので、pongより先に届くことがある
+    socket.send(auth(&private_id)).await.unwrap();
+    socket
+        .send(Message::Text(HEARTBEAT.to_owned()))
+        .await
+        .unwrap();
+    expect_text(&mut socket, HEARTBEAT_ACK).await;
+}
+
+#[tokio::test]
+async fn too_many_messages_before_the_first_pong_are_rejected() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let mut socket = server.connect(&room_id).await;
+    for _ in 0..20 {
+        socket
+            .send(Message::Text(HEARTBEAT.to_owned()))
+            .await
+            .unwrap();
+    }
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Policy);
+}
+
+#[tokio::test]
+async fn binary_messages_are_rejected() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Unsupported);
+}
+
+#[tokio::test]
+async fn invalid_authentication_is_rejected() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket
+        .send(Message::Text("hello".to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Invalid);
+    // 部屋にいないプレイヤーとしても認証できない
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket
+        .send(auth("00000000-0000-0000-0000-000000000000"))
+        .await
+        .unwrap();
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Invalid);
+}
+
+#[tokio::test]
+async fn invalid_actions_are_rejected() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let private_id = server.join(&room_id).await;
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket.send(auth(&private_id)).await.unwrap();
+    socket
+        .send(Message::Text(r#"{"t":-1}"#.to_owned()))
+        .await
+        .unwrap();
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Invalid);
+}
+
+#[tokio::test]
+async fn actions_work_after_authenticating() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let private_id = server.join(&room_id).await;
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket.send(auth(&private_id)).await.unwrap();
+    // 他に誰も見ていなくても、駒を選べる
+    socket
+        .send(Message::Text(r#"{"t":1,"c":[0,0]}"#.to_owned()))
+        .await
+        .unwrap();
+    socket
+        .send(Message::Text(HEARTBEAT.to_owned()))
+        .await
+        .unwrap();
+    expect_text(&mut socket, HEARTBEAT_ACK).await;
+    // クライアントからのpingにも応じる
+    socket.send(Message::Ping(vec![4, 5, 6])).await.unwrap();
+    loop {
+        match next_message(&mut socket).await {
+            Some(Message::Pong(payload)) => {
+                assert_eq!(payload, vec![4, 5, 6]);
+                break;
+            }
+            Some(Message::Close(frame)) => panic!("closed: {:?}", frame),
+            None => panic!("disconnected"),
+            Some(_) => {}
+        }
+    }
+    socket.close(None).await.unwrap();
+    while next_message(&mut socket).await.is_some() {}
+}
+
+#[tokio::test]
+async fn leaving_the_room_expires_the_session_but_keeps_the_connection() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let private_id = server.join(&room_id).await;
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket.send(auth(&private_id)).await.unwrap();
+    socket
+        .send(Message::Text(HEARTBEAT.to_owned()))
+        .await
+        .unwrap();
+    expect_text(&mut socket, HEARTBEAT_ACK).await;
+    server.leave(&room_id, &private_id).await;
+    expect_text(&mut socket, SESSION_EXPIRED).await;
+    // 観戦を続けながら、もう一度参加できる
+    let private_id = server.join(&room_id).await;
+    socket.send(auth(&private_id)).await.unwrap();
+    socket
+        .send(Message::Text(HEARTBEAT.to_owned()))
+        .await
+        .unwrap();
+    expect_text(&mut socket, HEARTBEAT_ACK).await;
+}
+
+#[tokio::test]
+async fn abrupt_disconnects_keep_the_seat_and_the_room_working() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let private_id = server.join(&room_id).await;
+    for _ in 0..3 {
+        let mut socket = server.connect_and_pong(&room_id).await;
+        socket.send(auth(&private_id)).await.unwrap();
+        socket
+            .send(Message::Text(HEARTBEAT.to_owned()))
+            .await
+            .unwrap();
+        expect_text(&mut socket, HEARTBEAT_ACK).await;
+        // 閉じる合図を送らずに切る
+        drop(socket);
+    }
+    // 最後の接続が切れたことが部屋に届くまで待つ
+    let mut is_disconnected = false;
+    for _ in 0..50 {
+        let room_data = server.room_data(&room_id).await;
+        let players = room_data["bottom_players"].as_array().unwrap();
+        assert_eq!(players.len(), 1);
+        if players[0]["is_disconnected"] == true {
+            is_disconnected = true;
+            break;
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    assert!(is_disconnected);
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket.send(auth(&private_id)).await.unwrap();
+    socket
+        .send(Message::Text(HEARTBEAT.to_owned()))
+        .await
+        .unwrap();
+    expect_text(&mut socket, HEARTBEAT_ACK).await;
+}
@@ -0,0 +1,44 @@
+//! Checks that a player connecting again closes their older connection when configured to.
+
+use futures_util::SinkExt as _;
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+use self::common::{auth, expect_close, expect_text, Server, HEARTBEAT, HEARTBEAT_ACK};
+
+mod common;
+
+const CONFIG: &str = r#"
+[player]
+kick_duplicate_connections = true
+
+[limit]
+# テストは全て同じIPアドレスから同時にリクエストする
+http_burst = 0
+
+[log]
+level = "error"
+"#;
+
+#[tokio::test]
+async fn older_connections_are_closed_when_connecting_elsewhere() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let private_id = server.join(&room_id).await;
+    let mut old_socket = server.connect_and_pong(&room_id).await;
+    old_socket.send(auth(&private_id)).await.unwrap();
+    old_socket
+        .send(Message::Text(HEARTBEAT.to_owned()))
+        .await
+        .unwrap();
+    expect_text(&mut old_socket, HEARTBEAT_ACK).await;
+    let mut new_socket = server.connect_and_pong(&room_id).await;
+    new_socket.send(auth(&private_id)).await.unwrap();
+    let frame = expect_close(&mut old_socket).await;
+    assert_eq!(frame.code, CloseCode::Normal);
+    assert_eq!(frame.reason, "Connected Elsewhere");
+    new_socket
+        .send(Message::Text(HEARTBEAT.to_owned()))
+        .await
+        .unwrap();
+    expect_text(&mut new_socket, HEARTBEAT_ACK).await;
+}
@@ -0,0 +1,34 @@
+//! Checks that the game WebSocket drops connections that stop answering pings.
+
+use std::time::Duration;
+
+use tokio::time::sleep;
+use tokio_tungstenite::tungstenite::Message;
+
+use self::common::{next_message, Server};
+
+mod common;
+
+const CONFIG: &str = r#"
+[player]
+pong_timeout = 1
+
+[limit]
+# テストは全て同じIPアドレスから同時にリクエストする
+http_burst = 0
+
+[log]
+level = "error"
+"#;
+
+#[tokio::test]
+async fn connections_without_a_pong_are_dropped() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let mut socket = server.connect(&room_id).await;
+    // 読まなければpongは送られない
+    sleep(Duration::from_secs(2)).await;
+    while let Some(msg) = next_message(&mut socket).await {
+        assert!(matches!(msg, Message::Ping(_)), "got {:?}", msg);
+    }
+}
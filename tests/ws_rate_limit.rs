@@ -0,0 +1,70 @@
+//! Checks the limit on the messages each WebSocket connection can send.
+
+use futures_util::SinkExt as _;
+use hyper::Method;
+use tokio_tungstenite::tungstenite::{protocol::frame::coding::CloseCode, Message};
+
+use self::common::{auth, expect_close, Server, Socket, HEARTBEAT};
+
+mod common;
+
+const CONFIG: &str = r#"
+[limit]
+# テストは全て同じIPアドレスから同時にリクエストする
+http_burst = 0
+ws_burst = 3
+ws_rate = 0.1
+
+[log]
+level = "error"
+"#;
+
+const NOTATION: &str = r#"[Game "numbers"]
+[Rules "1.0"]
+[Date "2024-01-01T12:00:00Z"]
+[BoardSize "8"]
+[BoardStyle "checker"]
+[FirstTurn "bottom"]
+[Result "*"]
+
+"#;
+
+async fn flood(socket: &mut Socket, text: &str) {
+    for _ in 0..5 {
+        socket.send(Message::Text(text.to_owned())).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn flooding_connections_are_closed() {
+    let server = Server::get(CONFIG);
+    let room_id = server.create_room().await;
+    let private_id = server.join(&room_id).await;
+    let mut socket = server.connect_and_pong(&room_id).await;
+    socket.send(auth(&private_id)).await.unwrap();
+    flood(&mut socket, HEARTBEAT).await;
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Policy);
+}
+
+#[tokio::test]
+async fn flooding_replay_connections_are_closed() {
+    let server = Server::get(CONFIG);
+    let response = server
+        .request(Method::POST, "/games/import", &[], NOTATION)
+        .await;
+    let data = serde_json::from_str::<serde_json::Value>(response.body()).unwrap();
+    let game_id = data["game_id"].as_str().expect("could not import the game");
+    let mut socket = server
+        .connect_to(&format!("/games/{}/replay/ws", game_id))
+        .await;
+    flood(&mut socket, r#"{"t":1}"#).await;
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Policy);
+}
+
+#[tokio::test]
+async fn flooding_lobby_connections_are_closed() {
+    let server = Server::get(CONFIG);
+    let mut socket = server.connect_to("/rooms/ws").await;
+    flood(&mut socket, "{}").await;
+    assert_eq!(expect_close(&mut socket).await.code, CloseCode::Policy);
+}
@@ -22,7 +22,7 @@ window.addEventListener("DOMContentLoaded", async () => {
     /** @type {Map<string, PlayerData>} */
     bottom: new Map(),
   };
-  let { roomId, boardSize, currentTurn, topPieces, bottomPieces } = await (async () => {
+  let { roomId, boardSize, phase, winner, currentTurn, currentTurnPlayer, topPieces, bottomPieces } = await (async () => {
     const res = await fetch(normalizedPath + "/room_data");
     /** @type {RawRoomData} */
     const data = await res.json();
@@ -31,6 +31,8 @@ window.addEventListener("DOMContentLoaded", async () => {
         name: player.name,
         selecting_piece: player.selecting_piece,
         is_inactive: player.is_inactive,
+        is_ready: player.is_ready,
+        is_disconnected: player.is_disconnected,
       })
     );
     data.bottom_players.forEach(player =>
@@ -38,12 +40,17 @@ window.addEventListener("DOMContentLoaded", async () => {
         name: player.name,
         selecting_piece: player.selecting_piece,
         is_inactive: player.is_inactive,
+        is_ready: player.is_ready,
+        is_disconnected: player.is_disconnected,
       })
     );
     return {
       roomId: data.room_id,
       boardSize: data.board_size,
+      phase: data.phase,
+      winner: data.winner,
       currentTurn: data.current_turn,
+      currentTurnPlayer: data.current_turn_player,
       topPieces: data.top_pieces,
       bottomPieces: data.bottom_pieces,
     };
@@ -334,7 +341,8 @@ window.addEventListener("DOMContentLoaded", async () => {
   redraw();
   let previousX = -1,
     previousY = -1,
-    isMovePieceMode = false;
+    isMovePieceMode = false,
+    isReady = false;
   drawObj.addComponentProducer(components => {
     if (isMovePieceMode) {
       components.push({
@@ -350,10 +358,21 @@ window.addEventListener("DOMContentLoaded", async () => {
     }
     /** @type {string} */
     let text;
-    if (isGuest) {
+    if (phase === "lobby") {
+      if (!isAuthorized) {
+        text = "Waiting for players";
+      } else {
+        text = isReady ? "Waiting for other players" : "Click to get ready";
+      }
+    } else if (phase === "finished") {
+      const winnerText = winner === playerSide ? "You win" : "You lose";
+      text = isAuthorized ? winnerText + " - click for a rematch" : winner + " player wins";
+    } else if (isGuest) {
       text = currentTurn.slice(0, 1).toUpperCase() + currentTurn.slice(1) + " player's turn";
+    } else if (currentTurn !== playerSide) {
+      text = "Enemy turn";
     } else {
-      text = currentTurn === playerSide ? "Your turn" : "Enemy turn";
+      text = currentTurnPlayer === null || currentTurnPlayer === publicId ? "Your turn" : "Teammate's turn";
     }
     components.push({
       type: ComponentType.Text,
@@ -396,8 +415,15 @@ window.addEventListener("DOMContentLoaded", async () => {
           return;
         }
       }
+      const name = prompt("名前を入力してください。(空欄の場合は自動で決まります)") ?? "";
       /** @type {CreateUserData} */
-      const res = await (await fetch(normalizedPath + "/join_" + side)).json();
+      const res = await (
+        await fetch(normalizedPath + "/players", {
+          method: "POST",
+          headers: { "Content-Type": "application/json" },
+          body: JSON.stringify({ side, name: name || null }),
+        })
+      ).json();
       if (res.success) {
         isAuthorized = true;
         drawObj.drawAll();
@@ -417,6 +443,8 @@ window.addEventListener("DOMContentLoaded", async () => {
           name: res.name,
           selecting_piece: null,
           is_inactive: false,
+          is_ready: false,
+          is_disconnected: false,
         });
         console.log("Logging in as:", res.name);
         sender.authorize(privateId);
@@ -425,6 +453,10 @@ window.addEventListener("DOMContentLoaded", async () => {
       } else {
         alert(`ユーザー登録に失敗しました。\n理由: ${res.message}`);
       }
+    } else if (phase === "lobby") {
+      sender.ready(!isReady);
+    } else if (phase === "finished") {
+      sender.requestRematch();
     } else {
       if (
         e.offsetX > calculatedValues.offsetX &&
@@ -467,6 +499,7 @@ window.addEventListener("DOMContentLoaded", async () => {
         /** @type {RawRoomData} */
         const data = await res.json();
         currentTurn = data.current_turn;
+        currentTurnPlayer = data.current_turn_player;
         topPieces = data.top_pieces;
         bottomPieces = data.bottom_pieces;
         redraw();
@@ -477,6 +510,8 @@ window.addEventListener("DOMContentLoaded", async () => {
           name: data.c,
           selecting_piece: null,
           is_inactive: false,
+          is_ready: false,
+          is_disconnected: false,
         });
         break;
       }
@@ -485,6 +520,8 @@ window.addEventListener("DOMContentLoaded", async () => {
           name: data.c,
           selecting_piece: null,
           is_inactive: false,
+          is_ready: false,
+          is_disconnected: false,
         });
         break;
       }
@@ -496,6 +533,129 @@ window.addEventListener("DOMContentLoaded", async () => {
         players.top.delete(data.i);
         break;
       }
+      case MessageType.PlayerRename: {
+        const player = players.top.get(data.i) ?? players.bottom.get(data.i);
+        if (player) {
+          player.name = data.c;
+        }
+        break;
+      }
+      case MessageType.PlayerReady: {
+        const player = players.top.get(data.i) ?? players.bottom.get(data.i);
+        if (player) {
+          player.is_ready = data.c;
+        }
+        if (data.i === publicId) {
+          isReady = data.c;
+        }
+        redraw();
+        break;
+      }
+      case MessageType.GameStart: {
+        phase = "playing";
+        redraw();
+        break;
+      }
+      case MessageType.PlayerKick: {
+        if (data.c === publicId) {
+          alert("ホストによって部屋から退出させられました。");
+        }
+        break;
+      }
+      case MessageType.RoomLock: {
+        console.log(data.c ? "Room locked" : "Room unlocked");
+        break;
+      }
+      case MessageType.SideChange: {
+        const [targetId, side] = data.c;
+        const player = players.top.get(targetId) ?? players.bottom.get(targetId);
+        if (player) {
+          players.top.delete(targetId);
+          players.bottom.delete(targetId);
+          player.selecting_piece = null;
+          player.is_ready = false;
+          players[side].set(targetId, player);
+        }
+        if (targetId === publicId) {
+          playerSide = side;
+          isReady = false;
+        }
+        redraw();
+        break;
+      }
+      case MessageType.BoardReset:
+      case MessageType.Rematch: {
+        const res = await fetch(normalizedPath + "/room_data");
+        /** @type {RawRoomData} */
+        const data = await res.json();
+        phase = data.phase;
+        winner = data.winner;
+        currentTurn = data.current_turn;
+        currentTurnPlayer = data.current_turn_player;
+        topPieces = data.top_pieces;
+        bottomPieces = data.bottom_pieces;
+        isReady = false;
+        // 再戦で側が入れ替わっている場合があるため、プレイヤーの一覧も作り直す
+        players.top.clear();
+        players.bottom.clear();
+        /** @type {const} */ (["top", "bottom"]).forEach(side =>
+          data[`${side}_players`].forEach(player => {
+            players[side].set(player.public_id, {
+              name: player.name,
+              selecting_piece: player.selecting_piece,
+              is_inactive: player.is_inactive,
+              is_ready: player.is_ready,
+              is_disconnected: player.is_disconnected,
+            });
+            if (player.public_id === publicId) {
+              playerSide = side;
+            }
+          })
+        );
+        redraw();
+        break;
+      }
+      case MessageType.GameEnd: {
+        phase = "finished";
+        winner = data.c;
+        redraw();
+        break;
+      }
+      case MessageType.RematchRequest: {
+        console.log("Rematch requested by:", data.i);
+        break;
+      }
+      case MessageType.HostTransfer: {
+        console.log("New host:", data.c);
+        break;
+      }
+      case MessageType.ServerNotice: {
+        alert(`運営からのお知らせ: ${data.c}`);
+        break;
+      }
+      case MessageType.PlayerInactive:
+      case MessageType.PlayerActive: {
+        const player = players.top.get(data.i) ?? players.bottom.get(data.i);
+        if (player) {
+          player.is_inactive = data.t === MessageType.PlayerInactive;
+        }
+        redraw();
+        break;
+      }
+      case MessageType.PlayerDisconnect:
+      case MessageType.PlayerReconnect: {
+        const player = players.top.get(data.i) ?? players.bottom.get(data.i);
+        if (player) {
+          player.is_disconnected = data.t === MessageType.PlayerDisconnect;
+        }
+        redraw();
+        break;
+      }
+      case MessageType.TurnPlayer: {
+        currentTurnPlayer = data.i;
+        redraw();
+        break;
+      }
       case MessageType.SessionExpired: {
         alert("セッションが期限切れになりました。");
         heartbeat.stop();
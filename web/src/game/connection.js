@@ -115,6 +115,62 @@ export function createCheckerConnection() {
           })
         );
       },
+      /**
+       * @param {boolean} isReady
+       */
+      ready(isReady) {
+        wsSend(
+          JSON.stringify({
+            t: 4,
+            c: isReady,
+          })
+        );
+      },
+      requestRematch() {
+        wsSend(JSON.stringify({ t: 11 }));
+      },
+      /**
+       * @param {string} name
+       */
+      rename(name) {
+        wsSend(
+          JSON.stringify({
+            t: 3,
+            c: name,
+          })
+        );
+      },
+      /**
+       * @param {string} publicId
+       */
+      kick(publicId) {
+        wsSend(JSON.stringify({ t: 5, c: publicId }));
+      },
+      /**
+       * @param {boolean} isLocked
+       */
+      lock(isLocked) {
+        wsSend(JSON.stringify({ t: 6, c: isLocked }));
+      },
+      /**
+       * @param {string} publicId
+       * @param {"top" | "bottom"} side
+       */
+      changeSide(publicId, side) {
+        wsSend(JSON.stringify({ t: 7, c: [publicId, side] }));
+      },
+      resetBoard() {
+        wsSend(JSON.stringify({ t: 8 }));
+      },
+      /**
+       * @param {string} publicId
+       */
+      transferHost(publicId) {
+        wsSend(JSON.stringify({ t: 9, c: publicId }));
+      },
+      startGame() {
+        wsSend(JSON.stringify({ t: 10 }));
+      },
     },
     receiver: msgIter,
     heartbeat: {
@@ -6,9 +6,28 @@ export const MessageType = Object.freeze({
   BottomPlayerJoin: 4,
   TopPlayerLeave: 5,
   BottomPlayerLeave: 6,
+  PlayerRename: 7,
+  TurnPlayer: 8,
+  PlayerReady: 9,
+  GameStart: 10,
+  PlayerKick: 11,
+  RoomLock: 12,
+  SideChange: 13,
+  BoardReset: 14,
+  HostTransfer: 15,
+  GameEnd: 16,
+  RematchRequest: 17,
+  Rematch: 18,
+  ServerNotice: 19,
+  PlayerInactive: 20,
+  PlayerActive: 21,
+  PlayerDisconnect: 22,
+  PlayerReconnect: 23,
   HeartbeatAck: 100,
   NotAccepted: 101,
   SessionExpired: 102,
   GotBinary: 103,
   GotInvalidData: 104,
+  TooManyMessages: 105,
+  ConnectedElsewhere: 106,
 });
@@ -11,8 +11,99 @@ const buttonsDiv = document.createElement("div");
   addClickHandlerAndAppendDiv(createRoomButton, () => {
     location.href = "/room/new";
   });
+  const createPublicRoomButton = document.createElement("span");
+  createPublicRoomButton.textContent = "公開の部屋を作成";
+  addClickHandlerAndAppendDiv(createPublicRoomButton, () => {
+    location.href = "/room/new?visibility=public";
+  });
+  const randomMatchButton = document.createElement("span");
+  randomMatchButton.textContent = "ランダムマッチ";
+  /** @type {string | null} */
+  let ticketId = null;
+  addClickHandlerAndAppendDiv(randomMatchButton, async () => {
+    // 待っている間にもう一度押すと、取り消す
+    if (ticketId) {
+      await fetch(`/matchmaking/${ticketId}`, { method: "DELETE" });
+      return;
+    }
+    const res = await fetch("/matchmaking/join", {
+      method: "POST",
+      headers: { "Content-Type": "application/json" },
+      body: "{}",
+    }).then(res => res.json());
+    if (!res.success) {
+      alert(`ランダムマッチに参加できませんでした: ${res.message}`);
+      return;
+    }
+    ticketId = res.ticket_id;
+    randomMatchButton.textContent = "対戦相手を探しています...(押して取り消す)";
+    for (;;) {
+      const status = await fetch(`/matchmaking/${ticketId}`).then(res => res.json());
+      if (status.status === "waiting") {
+        continue;
+      }
+      ticketId = null;
+      randomMatchButton.textContent = "ランダムマッチ";
+      if (status.status === "matched") {
+        sessionStorage.setItem(
+          status.room_id,
+          JSON.stringify({
+            privateId: status.private_id,
+            publicId: status.public_id,
+            playerSide: status.side,
+          })
+        );
+        location.href = `/room/${status.room_id}`;
+      } else if (status.status === "timed_out") {
+        alert("対戦相手が見つかりませんでした");
+      }
+      return;
+    }
+  });
   const howToPlayButton = document.createElement("span");
   howToPlayButton.textContent = "遊び方(未実装)";
   addClickHandlerAndAppendDiv(howToPlayButton, () => {});
 }
 document.body.appendChild(buttonsDiv);
+
+// 参加者を募集している公開の部屋の一覧
+const roomListDiv = document.createElement("div");
+document.body.appendChild(roomListDiv);
+{
+  const LobbyMessageType = Object.freeze({ List: 1, Update: 2, Remove: 3 });
+  const rooms = new Map();
+  const render = () => {
+    roomListDiv.replaceChildren(
+      ...[...rooms.values()].map(room => {
+        const element = document.createElement("span");
+        const host = room.host_name ?? "ホスト不在";
+        element.textContent = `${host} ${room.board_size}x${room.board_size} 上${room.seats.top}/${room.team_player_limit} 下${room.seats.bottom}/${room.team_player_limit}`;
+        element.addEventListener("click", () => {
+          location.href = `/room/${room.room_id}`;
+        });
+        return element;
+      })
+    );
+  };
+  const secure = location.protocol === "https:" ? "s" : "";
+  const ws = new WebSocket(`ws${secure}://${location.host}/rooms/ws`);
+  ws.addEventListener("message", message => {
+    const data = JSON.parse(message.data);
+    switch (data.t) {
+      case LobbyMessageType.List: {
+        rooms.clear();
+        data.c.forEach(room => rooms.set(room.room_id, room));
+        break;
+      }
+      case LobbyMessageType.Update: {
+        rooms.set(data.c.room_id, data.c);
+        break;
+      }
+      case LobbyMessageType.Remove: {
+        rooms.delete(data.c);
+        break;
+      }
+    }
+    render();
+  });
+}
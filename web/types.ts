@@ -1,12 +1,28 @@
 export type Position = [number, number];
-export type PlayerData = { name: string; selecting_piece: Position | null; is_inactive: boolean };
+export type PlayerData = {
+  name: string;
+  selecting_piece: Position | null;
+  is_inactive: boolean;
+  is_ready: boolean;
+  is_disconnected: boolean;
+};
 type PlayerDataWithId = PlayerData & { public_id: string };
 export type PieceData = { position: Position; number: number };
 export type Side = "top" | "bottom";
+export type TeamMode = "free_for_all" | "rotation" | "captain";
+export type GamePhase = "lobby" | "playing" | "finished";
 export type RawRoomData = {
   room_id: string;
   board_size: number;
+  team_mode: TeamMode;
+  rated: boolean;
+  game_id: string | null;
+  phase: GamePhase;
+  winner: Side | null;
+  host: string | null;
+  is_locked: boolean;
   current_turn: Side;
+  current_turn_player: string | null;
   top_players: PlayerDataWithId[];
   top_pieces: PieceData[];
   bottom_players: PlayerDataWithId[];
@@ -16,8 +32,43 @@ export type CreateUserData =
   | { success: true; side: Side; private_id: string; public_id: string; name: string }
   | { success: false; message: string };
 type PlayerAction = { t: 1; c: Position } | { t: 2; c: [Position, Position] };
-type PublicEvent = (PlayerAction | { t: 3; c: string } | { t: 4; c: string } | { t: 5 } | { t: 6 }) & { i: string };
-type PrivateEvent = { t: 100 } | { t: 101; c: PlayerAction } | { t: 102 } | { t: 103 } | { t: 104 };
+type SentAction =
+  | PlayerAction
+  | { t: 3; c: string }
+  | { t: 4; c: boolean }
+  | { t: 5; c: string }
+  | { t: 6; c: boolean }
+  | { t: 7; c: [string, Side] }
+  | { t: 8 }
+  | { t: 9; c: string }
+  | { t: 10 }
+  | { t: 11 }
+  | { t: 99 };
+type PublicEvent = (
+  | PlayerAction
+  | { t: 3; c: string }
+  | { t: 4; c: string }
+  | { t: 5 }
+  | { t: 6 }
+  | { t: 7; c: string }
+  | { t: 8 }
+  | { t: 9; c: boolean }
+  | { t: 10 }
+  | { t: 11; c: string }
+  | { t: 12; c: boolean }
+  | { t: 13; c: [string, Side] }
+  | { t: 14 }
+  | { t: 15; c: string }
+  | { t: 16; c: Side }
+  | { t: 17 }
+  | { t: 18 }
+  | { t: 19; c: string }
+  | { t: 20 }
+  | { t: 21 }
+  | { t: 22 }
+  | { t: 23 }
+) & { i: string };
+type PrivateEvent = { t: 100 } | { t: 101; c: SentAction } | { t: 102 } | { t: 103 } | { t: 104 } | { t: 105 } | { t: 106 };
 export type ReceivedEvent = PublicEvent | PrivateEvent;
 export type CanvasComponent =
   | { type: 1; color: CanvasFillStrokeStyles["fillStyle"]; x: number; y: number; w: number; h: number }